@@ -1,17 +1,46 @@
 use crate::token::Token;
 use crate::value::Value;
+use crate::pattern::Pattern;
+use crate::span::Span;
 use serde::{Serialize, Deserialize};
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
-pub enum ASTNode {
+pub struct ASTNode {
+    pub kind: ASTKind,
+    pub span: Span,
+}
+
+impl ASTNode {
+    pub fn new(kind: ASTKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+/// Where a variable lives at runtime: `depth` scopes out from the one it is used in, at
+/// position `index` of that scope. Filled in by the resolver.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum ASTKind {
     FunctionDefinition {
         params: Vec<String>,
         body: Box<ASTNode>,
     },
 
+    FunctionDeclaration {
+        name: String,
+        params: Vec<String>,
+        body: Box<ASTNode>,
+        slot: Option<Slot>,
+    },
+
     FunctionCall {
-        function: Option<String>,
-        arguments: Box<Vec<ASTNode>>,
+        function: Box<ASTNode>,
+        arguments: Vec<ASTNode>,
     },
 
     BinaryOperation {
@@ -32,11 +61,29 @@ pub enum ASTNode {
     },
 
     Block {
-        statements: Box<Vec<ASTNode>>,
+        statements: Vec<ASTNode>,
     },
 
     Assignment {
-        name: Box<String>,
+        name: String,
+        value: Box<ASTNode>,
+        slot: Option<Slot>,
+    },
+
+    /// `name[i][j] = value`, replacing an item of the vector stored in a variable, or
+    /// `name[i] += value` and the like with an `operator`.
+    IndexAssignment {
+        name: String,
+        indices: Vec<ASTNode>,
+        operator: Option<Token>,
+        value: Box<ASTNode>,
+        slot: Option<Slot>,
+    },
+
+    /// `pattern = value`, assigning what the pattern binds to existing variables. Inside
+    /// a `let` it declares them instead.
+    Destructure {
+        pattern: Pattern,
         value: Box<ASTNode>,
     },
 
@@ -50,23 +97,59 @@ pub enum ASTNode {
         false_branch: Option<Box<ASTNode>>,
     },
 
+    /// `while condition body`, or `loop body` with a `true` condition.
     Loop {
         condition: Box<ASTNode>,
         body: Box<ASTNode>,
+        label: Option<String>,
+    },
+
+    /// `for variable in iterable body`, see `Iteration`.
+    For {
+        variable: String,
+        iterable: Box<ASTNode>,
+        body: Box<ASTNode>,
+        slot: Option<Slot>,
+        label: Option<String>,
+    },
+
+    /// `match scrutinee { pattern if guard => body, ... }`: the first arm whose pattern
+    /// matches and whose guard holds is evaluated.
+    Match {
+        scrutinee: Box<ASTNode>,
+        arms: Vec<MatchArm>,
     },
 
     Literal(Value),
-    Identifier(Box<String>),
+    Interpolation(Vec<ASTNode>),
+    Identifier {
+        name: String,
+        slot: Option<Slot>,
+    },
 
-    Tuple(Box<Vec<ASTNode>>),
-    Vector(Box<Vec<ASTNode>>),
+    Tuple(Vec<ASTNode>),
+    Vector(Vec<ASTNode>),
     Index {
         expression: Box<ASTNode>,
         index: Box<ASTNode>,
     },
 
     Return(Box<ASTNode>),
-    Break,
+    /// `break`, `break 'label` or `break value`: without a label, the innermost loop ends.
+    Break {
+        label: Option<String>,
+        value: Option<Box<ASTNode>>,
+    },
+    Continue {
+        label: Option<String>,
+    },
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<ASTNode>,
+    pub body: ASTNode,
 }
 
 pub trait AstRef {
@@ -84,4 +167,3 @@ impl AstRef for Box<ASTNode> {
         self.as_ref()
     }
 }
-
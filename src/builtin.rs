@@ -1,32 +1,64 @@
 use crate::value::Value;
 use crate::environment::Environment;
+use crate::resolver::Resolver;
+use crate::error::LimError;
+use crate::token::Token;
 use chrono::Utc;
+use num_rational::BigRational;
 
-pub fn initialization(env: &mut Environment) -> Result<(), String> {
-    env.define("timestamp".to_string(), Value::Hole(1))?;
-    env.define("printf".to_string(), Value::Hole(12))?;
+pub fn initialization(resolver: &mut Resolver, env: &mut Environment) -> Result<(), LimError> {
+    for (name, value) in [
+        ("timestamp", Value::Hole(1)),
+        ("printf", Value::Hole(12)),
+        ("throw", Value::Hole(13)),
+        ("rational", Value::Hole(14)),
+    ] {
+        env.define(resolver.define_global(name)?, value);
+    }
     Ok(())
 }
 
-pub fn hole_func(id: u32, args: Vec<Value>) -> Result<Value, String> {
+pub fn hole_func(id: u32, args: Vec<Value>) -> Result<Value, LimError> {
     match id {
         1 => {
-            Ok(Value::Number(Utc::now().timestamp() as f64))
+            Ok(Value::Int(Utc::now().timestamp()))
         },
         12 => {
+            if args.is_empty() {
+                return Err(LimError::arity("printf expected at least 1 argument, but got 0"));
+            }
+
             if let Value::String(format) = &args[0] {
                 let formatted = format_string(format, &args[1..])?;
                 print!("{}", formatted);
                 Ok(Value::Nothing)
             } else {
-                Err(format!("The first argument must be a string, actually found: {}", args[0]))
+                Err(LimError::type_error(format!("The first argument must be a string, actually found: {}", args[0])))
+            }
+        },
+        13 => {
+            let message = args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>().join(" ");
+            Err(LimError::thrown(message))
+        },
+        14 => {
+            let exact = |value: &Value| match value {
+                Value::Number(n) => BigRational::from_float(*n)
+                    .ok_or_else(|| LimError::type_error(format!("Cannot convert {} to a rational", n))),
+                _ => value.to_rational()
+                    .ok_or_else(|| LimError::type_error(format!("Expected a number, found: {}", value))),
+            };
+
+            match &args[..] {
+                [value] => Ok(Value::Rational(exact(value)?)),
+                [numerator, denominator] => Value::Rational(exact(numerator)?).arithmetic(&Token::Div, &Value::Rational(exact(denominator)?)),
+                _ => Err(LimError::arity(format!("rational expected 1 or 2 arguments, but got {}", args.len()))),
             }
         },
-        _ => Err(format!("No hole func: {id}")),
+        _ => Err(LimError::runtime(format!("No hole func: {id}"))),
     }
 }
 
-fn format_string(format: &str, args: &[Value]) -> Result<String, String> {
+fn format_string(format: &str, args: &[Value]) -> Result<String, LimError> {
     let mut result = String::new();
     let mut arg_index = 0;
 
@@ -39,7 +71,7 @@ fn format_string(format: &str, args: &[Value]) -> Result<String, String> {
                     result.push_str(&args[arg_index].to_string());
                     arg_index += 1;
                 } else {
-                    return Err("Not enough arguments for format string".to_string());
+                    return Err(LimError::arity("Not enough arguments for format string"));
                 }
             } else {
                 result.push(ch);
@@ -50,7 +82,7 @@ fn format_string(format: &str, args: &[Value]) -> Result<String, String> {
     }
 
     if arg_index < args.len() {
-        Err(format!("Too many arguments for format string. Need {}, found {}", arg_index, args.len()))
+        Err(LimError::arity(format!("Too many arguments for format string. Need {}, found {}", arg_index, args.len())))
     } else {
         Ok(result)
     }
@@ -0,0 +1,115 @@
+use serde::{Serialize, Deserialize};
+
+use crate::ast_node::Slot;
+use crate::pattern::Pattern;
+use crate::span::Span;
+use crate::token::Token;
+use crate::value::Value;
+
+/// One operation of the stack VM. Every expression leaves exactly one value on the stack.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Instruction {
+    /// Pushes a constant of the running function.
+    Constant(usize),
+    Pop,
+
+    /// Pushes a variable. `name` indexes the function's names, for error messages.
+    GetVar { slot: Slot, name: usize },
+    /// Assigns the top of the stack to a variable, leaving it on the stack.
+    SetVar { slot: Slot, name: usize },
+    /// Pops a value into a slot of the current scope.
+    DefineVar(usize),
+    /// Pops a value and the given number of indices below it, and replaces the item they
+    /// select in the vector a variable holds, see `Value::assign_index`. Pushes the item.
+    SetIndex { slot: Slot, name: usize, indices: usize, operator: Option<Token> },
+    /// Takes the top of the stack apart with a pattern of the running function. With
+    /// `define`, pops it and defines the bindings in the current scope, like `DefineVar`;
+    /// otherwise assigns them, leaving it on the stack, like `SetVar`.
+    Destructure { pattern: usize, define: bool },
+
+    /// An arithmetic or bitwise operator, see `Value::arithmetic`.
+    Binary(Token),
+    /// A comparison, see `Value::relation`.
+    Compare(Token),
+    Unary(Token),
+    /// Fails unless the top of the stack is a boolean, for the operands of `&&` and `||`.
+    CheckBoolean,
+
+    Jump(usize),
+    /// Pops the condition and jumps unless it is `true`.
+    JumpIfFalse(usize),
+    /// Jumps if the top of the stack is `false`, keeping it; otherwise pops it.
+    JumpIfFalseOrPop(usize),
+    /// Jumps if the top of the stack is `true`, keeping it; otherwise pops it.
+    JumpIfTrueOrPop(usize),
+
+    PushScope,
+    PopScope,
+    /// Marks the start of a loop, recording where `break` continues and what it unwinds to.
+    EnterLoop(usize),
+    /// Pops a value and marks the start of a `for` loop over it, like `EnterLoop`.
+    Iterate(usize),
+    /// Pushes the next item of the innermost `for` loop, or jumps if there are no more.
+    Next(usize),
+    ExitLoop,
+    /// Pops the value of a `break` and leaves the given number of loops besides the
+    /// innermost one, then that one.
+    Break(usize),
+    /// Leaves `depth` loops like `break`, unwinds the next one without leaving it, and
+    /// jumps to its next iteration.
+    Continue { depth: usize, next: usize },
+
+    /// Matches the top of the stack against a pattern of the running function. If it
+    /// matches, opens a scope holding what the pattern binds; otherwise jumps.
+    Match { pattern: usize, otherwise: usize },
+    /// Fails with the value on top of the stack, which no arm of a `match` matched.
+    NoMatch,
+
+    /// Creates a closure over the current scope for a function of the program.
+    Closure(usize),
+    /// Calls the value below the given number of arguments.
+    Call(usize),
+    /// A call whose result is returned: a compiled function replaces the running one
+    /// instead of nesting. Always followed by `Return`, for other callees.
+    TailCall(usize),
+    Return,
+
+    Tuple(usize),
+    Vector(usize),
+    Index,
+    Interpolate(usize),
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Function {
+    /// The name it was declared or first bound with, for error messages.
+    pub name: Option<String>,
+    pub arity: usize,
+    pub code: Vec<Instruction>,
+    /// The source location of each instruction, for error messages.
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    pub names: Vec<String>,
+    pub patterns: Vec<Pattern>,
+}
+
+impl Function {
+    pub fn new(name: Option<String>, arity: usize) -> Self {
+        Self {
+            name,
+            arity,
+            code: vec![],
+            spans: vec![],
+            constants: vec![],
+            names: vec![],
+            patterns: vec![],
+        }
+    }
+}
+
+/// A compiled program: its functions, and which of them is the top-level code.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub entry: usize,
+}
@@ -0,0 +1,453 @@
+use crate::ast_node::{ASTNode, ASTKind};
+use crate::bytecode::{Function, Instruction, Program};
+use crate::error::LimError;
+use crate::pattern::Pattern;
+use crate::resolver::resolved;
+use crate::span::Span;
+use crate::token::Token;
+use crate::value::Value;
+
+/// A loop enclosing the instruction being compiled, and where its next iteration starts.
+struct Target {
+    label: Option<String>,
+    next: usize,
+}
+
+/// The function being compiled, and the loops enclosing the current instruction.
+struct Context {
+    function: Function,
+    loops: Vec<Target>,
+}
+
+/// Compiles a resolved AST to bytecode for the `Vm`. The code opens and closes scopes at
+/// exactly the points `Interpreter::evaluate` does, which is what keeps the slots the
+/// resolver assigned valid for both.
+pub struct Compiler {
+    functions: Vec<Function>,
+    contexts: Vec<Context>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            functions: vec![],
+            contexts: vec![],
+        }
+    }
+
+    pub fn compile(mut self, program: &ASTNode) -> Result<Program, LimError> {
+        self.contexts.push(Context { function: Function::new(None, 0), loops: vec![] });
+
+        // Top-level statements run in the global scope, as in `Interpreter::execute`.
+        match &program.kind {
+            ASTKind::Block { statements } => self.statements(statements, program.span)?,
+            _ => self.node(program)?,
+        }
+        self.emit(Instruction::Return, program.span);
+
+        let entry = self.finish();
+        Ok(Program { functions: self.functions, entry })
+    }
+
+    fn context(&mut self) -> &mut Context {
+        self.contexts.last_mut().expect("compiling outside of a function")
+    }
+
+    fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+        let function = &mut self.context().function;
+        function.code.push(instruction);
+        function.spans.push(span);
+        function.code.len() - 1
+    }
+
+    fn constant(&mut self, value: Value, span: Span) {
+        let constants = &mut self.context().function.constants;
+        // Floats are never shared: `0.0 == -0.0`, but they are different constants.
+        let shared = !matches!(value, Value::Number(_));
+        let index = constants.iter().position(|constant| shared && *constant == value).unwrap_or_else(|| {
+            constants.push(value);
+            constants.len() - 1
+        });
+        self.emit(Instruction::Constant(index), span);
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        let names = &mut self.context().function.names;
+        names.iter().position(|known| known == name).unwrap_or_else(|| {
+            names.push(name.to_string());
+            names.len() - 1
+        })
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) -> usize {
+        let patterns = &mut self.context().function.patterns;
+        patterns.push(pattern.clone());
+        patterns.len() - 1
+    }
+
+    fn destructure(&mut self, pattern: &Pattern, define: bool, span: Span) {
+        let pattern = self.pattern(pattern);
+        self.emit(Instruction::Destructure { pattern, define }, span);
+    }
+
+    fn here(&mut self) -> usize {
+        self.context().function.code.len()
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.context().function.code[at] {
+            Instruction::Jump(to)
+            | Instruction::JumpIfFalse(to)
+            | Instruction::JumpIfFalseOrPop(to)
+            | Instruction::JumpIfTrueOrPop(to)
+            | Instruction::EnterLoop(to)
+            | Instruction::Iterate(to)
+            | Instruction::Next(to)
+            | Instruction::Match { otherwise: to, .. } => *to = target,
+            instruction => unreachable!("not a jump: {:?}", instruction),
+        }
+    }
+
+    /// How many loops out from the innermost one a `break` or `continue` with `label`
+    /// goes; `None` when it is outside of any loop, and ends the function.
+    fn target(&mut self, label: &Option<String>, span: Span) -> Result<Option<usize>, LimError> {
+        let loops = &self.context().loops;
+        match label {
+            None => Ok(if loops.is_empty() { None } else { Some(0) }),
+            Some(name) => loops.iter().rev().position(|target| target.label == *label).map(Some)
+                .ok_or_else(|| LimError::name(format!("Undeclared label: '{}", name)).at(span)),
+        }
+    }
+
+    fn finish(&mut self) -> usize {
+        let context = self.contexts.pop().expect("compiling outside of a function");
+        self.functions.push(context.function);
+        self.functions.len() - 1
+    }
+
+    fn function(&mut self, name: Option<&str>, params: &[String], body: &ASTNode) -> Result<usize, LimError> {
+        self.contexts.push(Context { function: Function::new(name.map(str::to_string), params.len()), loops: vec![] });
+        self.node(body)?;
+        self.emit(Instruction::Return, body.span);
+        Ok(self.finish())
+    }
+
+    fn statements(&mut self, statements: &[ASTNode], span: Span) -> Result<(), LimError> {
+        for statement in statements {
+            if let ASTKind::FunctionDeclaration { name, params, body, slot } = &statement.kind {
+                let function = self.function(Some(name), params, body)?;
+                self.emit(Instruction::Closure(function), statement.span);
+                self.emit(Instruction::DefineVar(resolved(slot, name)?.index), statement.span);
+            }
+        }
+
+        if statements.is_empty() {
+            self.constant(Value::Null, span);
+        }
+
+        for (i, statement) in statements.iter().enumerate() {
+            if i > 0 {
+                self.emit(Instruction::Pop, statement.span);
+            }
+
+            match statement.kind {
+                ASTKind::FunctionDeclaration { .. } => self.constant(Value::Null, statement.span),
+                _ => self.node(statement)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scoped(&mut self, node: &ASTNode) -> Result<(), LimError> {
+        self.emit(Instruction::PushScope, node.span);
+        match &node.kind {
+            ASTKind::Block { statements } => self.statements(statements, node.span)?,
+            _ => self.node(node)?,
+        }
+        self.emit(Instruction::PopScope, node.span);
+        Ok(())
+    }
+
+    fn node(&mut self, node: &ASTNode) -> Result<(), LimError> {
+        let span = node.span;
+
+        match &node.kind {
+            ASTKind::Block { .. } => self.scoped(node)?,
+
+            ASTKind::Let { ast } => {
+                let (name, slot) = match &ast.kind {
+                    ASTKind::Identifier { name, slot } => {
+                        self.constant(Value::Null, span);
+                        (name, slot)
+                    },
+                    ASTKind::Assignment { name, value, slot } => {
+                        // `let f = fn ...` names the function after the variable.
+                        match &value.kind {
+                            ASTKind::FunctionDefinition { params, body } => {
+                                let function = self.function(Some(name), params, body)?;
+                                self.emit(Instruction::Closure(function), value.span);
+                            },
+                            _ => self.node(value)?,
+                        }
+                        (name, slot)
+                    },
+                    ASTKind::Destructure { pattern, value } => {
+                        self.node(value)?;
+                        self.destructure(pattern, true, ast.span);
+                        self.constant(Value::Null, span);
+                        return Ok(());
+                    },
+                    _ => return Err(LimError::runtime(format!("Cannot declare this: {:?}", ast.kind)).at(ast.span)),
+                };
+
+                self.emit(Instruction::DefineVar(resolved(slot, name).map_err(|e| e.at(ast.span))?.index), ast.span);
+                self.constant(Value::Null, span);
+            },
+
+            ASTKind::FunctionDeclaration { name, params, body, slot } => {
+                let function = self.function(Some(name), params, body)?;
+                self.emit(Instruction::Closure(function), span);
+                self.emit(Instruction::DefineVar(resolved(slot, name).map_err(|e| e.at(span))?.index), span);
+                self.constant(Value::Null, span);
+            },
+
+            ASTKind::FunctionDefinition { params, body } => {
+                let function = self.function(None, params, body)?;
+                self.emit(Instruction::Closure(function), span);
+            },
+
+            ASTKind::FunctionCall { function, arguments } => {
+                self.node(function)?;
+                for argument in arguments {
+                    self.node(argument)?;
+                }
+                self.emit(Instruction::Call(arguments.len()), span);
+            },
+
+            ASTKind::Conditional { condition, true_branch, false_branch } => {
+                self.node(condition)?;
+                let otherwise = self.emit(Instruction::JumpIfFalse(0), span);
+                self.scoped(true_branch)?;
+                let end = self.emit(Instruction::Jump(0), span);
+
+                self.patch(otherwise);
+                match false_branch {
+                    Some(false_branch) => self.scoped(false_branch)?,
+                    None => self.constant(Value::Null, span),
+                }
+                self.patch(end);
+            },
+
+            ASTKind::Loop { condition, body, label } => {
+                // The stack holds the value of the last iteration, `null` before the first.
+                let exit = self.emit(Instruction::EnterLoop(0), span);
+                self.constant(Value::Null, span);
+
+                let start = self.here();
+                self.node(condition)?;
+                let done = self.emit(Instruction::JumpIfFalse(0), span);
+                self.emit(Instruction::Pop, span);
+
+                self.context().loops.push(Target { label: label.clone(), next: start });
+                self.scoped(body)?;
+                self.context().loops.pop();
+
+                self.emit(Instruction::Jump(start), span);
+                self.patch(done);
+                self.emit(Instruction::ExitLoop, span);
+                self.patch(exit);
+            },
+
+            ASTKind::For { variable, iterable, body, slot, label } => {
+                // As for `while`, the stack holds the value of the last iteration.
+                self.node(iterable)?;
+                let exit = self.emit(Instruction::Iterate(0), iterable.span);
+                self.constant(Value::Null, span);
+
+                let next = self.here();
+                let done = self.emit(Instruction::Next(0), iterable.span);
+                self.emit(Instruction::PushScope, span);
+                self.emit(Instruction::DefineVar(resolved(slot, variable).map_err(|e| e.at(span))?.index), span);
+                self.emit(Instruction::Pop, span);
+
+                self.context().loops.push(Target { label: label.clone(), next });
+                self.scoped(body)?;
+                self.context().loops.pop();
+
+                self.emit(Instruction::PopScope, span);
+                self.emit(Instruction::Jump(next), span);
+                self.patch(done);
+                self.emit(Instruction::ExitLoop, span);
+                self.patch(exit);
+            },
+
+            // Outside of a loop, `break` and `continue` end the function like the
+            // interpreter's do.
+            ASTKind::Break { label, value } => {
+                match value {
+                    Some(value) => self.node(value)?,
+                    None => self.constant(Value::Null, span),
+                }
+                match self.target(label, span)? {
+                    Some(depth) => self.emit(Instruction::Break(depth), span),
+                    None => self.emit(Instruction::Return, span),
+                };
+            },
+
+            ASTKind::Continue { label } => match self.target(label, span)? {
+                Some(depth) => {
+                    let loops = &self.context().loops;
+                    let next = loops[loops.len() - 1 - depth].next;
+                    self.emit(Instruction::Continue { depth, next }, span);
+                },
+                None => {
+                    self.constant(Value::Null, span);
+                    self.emit(Instruction::Return, span);
+                },
+            },
+
+            // Only inside a function: a tail call replaces the function's own frame.
+            ASTKind::Return(value) => match &value.kind {
+                ASTKind::FunctionCall { function, arguments } if self.contexts.len() > 1 => {
+                    self.node(function)?;
+                    for argument in arguments {
+                        self.node(argument)?;
+                    }
+                    self.emit(Instruction::TailCall(arguments.len()), value.span);
+                    self.emit(Instruction::Return, span);
+                },
+                _ => {
+                    self.node(value)?;
+                    self.emit(Instruction::Return, span);
+                },
+            },
+
+            // The scrutinee stays on the stack until an arm is chosen. A matching pattern
+            // opens the scope of its bindings, which a failing guard closes again.
+            ASTKind::Match { scrutinee, arms } => {
+                self.node(scrutinee)?;
+                let mut ends = vec![];
+
+                for arm in arms {
+                    let pattern = self.pattern(&arm.pattern);
+                    let otherwise = self.emit(Instruction::Match { pattern, otherwise: 0 }, scrutinee.span);
+
+                    let guard = match &arm.guard {
+                        Some(guard) => {
+                            self.node(guard)?;
+                            Some(self.emit(Instruction::JumpIfFalse(0), guard.span))
+                        },
+                        None => None,
+                    };
+
+                    self.emit(Instruction::Pop, arm.body.span);
+                    self.scoped(&arm.body)?;
+                    self.emit(Instruction::PopScope, arm.body.span);
+                    ends.push(self.emit(Instruction::Jump(0), arm.body.span));
+
+                    if let Some(guard) = guard {
+                        self.patch(guard);
+                        self.emit(Instruction::PopScope, arm.body.span);
+                    }
+                    self.patch(otherwise);
+                }
+
+                self.emit(Instruction::NoMatch, scrutinee.span);
+                for end in ends {
+                    self.patch(end);
+                }
+            },
+
+            ASTKind::BinaryOperation { operator, left, right } => {
+                self.node(left)?;
+                self.node(right)?;
+                self.emit(Instruction::Binary(operator.clone()), span);
+            },
+
+            ASTKind::LogicalOperation { operator: operator @ (Token::And | Token::Or), left, right } => {
+                self.node(left)?;
+                self.emit(Instruction::CheckBoolean, left.span);
+                let end = match operator {
+                    Token::And => self.emit(Instruction::JumpIfFalseOrPop(0), span),
+                    _ => self.emit(Instruction::JumpIfTrueOrPop(0), span),
+                };
+                self.node(right)?;
+                self.emit(Instruction::CheckBoolean, right.span);
+                self.patch(end);
+            },
+
+            ASTKind::LogicalOperation { operator, left, right } => {
+                self.node(left)?;
+                self.node(right)?;
+                self.emit(Instruction::Compare(operator.clone()), span);
+            },
+
+            ASTKind::UnaryOperation { operator, operand } => {
+                self.node(operand)?;
+                self.emit(Instruction::Unary(operator.clone()), span);
+            },
+
+            ASTKind::Literal(value) => self.constant(value.clone(), span),
+
+            ASTKind::Interpolation(parts) => {
+                for part in parts {
+                    self.node(part)?;
+                }
+                self.emit(Instruction::Interpolate(parts.len()), span);
+            },
+
+            ASTKind::Identifier { name, slot } => {
+                let slot = resolved(slot, name).map_err(|e| e.at(span))?;
+                let name = self.name(name);
+                self.emit(Instruction::GetVar { slot, name }, span);
+            },
+
+            ASTKind::Assignment { name, value, slot } => {
+                self.node(value)?;
+                let slot = resolved(slot, name).map_err(|e| e.at(span))?;
+                let name = self.name(name);
+                self.emit(Instruction::SetVar { slot, name }, span);
+            },
+
+            ASTKind::IndexAssignment { name, indices, operator, value, slot } => {
+                for index in indices {
+                    self.node(index)?;
+                }
+                self.node(value)?;
+                let slot = resolved(slot, name).map_err(|e| e.at(span))?;
+                let name = self.name(name);
+                self.emit(Instruction::SetIndex { slot, name, indices: indices.len(), operator: operator.clone() }, span);
+            },
+
+            ASTKind::Destructure { pattern, value } => {
+                self.node(value)?;
+                self.destructure(pattern, false, span);
+            },
+
+            ASTKind::Tuple(items) => {
+                for item in items {
+                    self.node(item)?;
+                }
+                self.emit(Instruction::Tuple(items.len()), span);
+            },
+
+            ASTKind::Vector(items) => {
+                for item in items {
+                    self.node(item)?;
+                }
+                self.emit(Instruction::Vector(items.len()), span);
+            },
+
+            ASTKind::Index { expression, index } => {
+                self.node(expression)?;
+                self.node(index)?;
+                self.emit(Instruction::Index, span);
+            },
+        }
+
+        Ok(())
+    }
+}
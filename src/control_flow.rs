@@ -1,17 +1,27 @@
+use crate::span::Span;
 use crate::value::Value;
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum ControlFlow {
-    Continue(Value),
+    /// Evaluation finished normally with a value.
+    Normal(Value),
     Return(Value),
-    Break,
+    /// `break`, for the loop with the label or else the innermost one, which evaluates to
+    /// the value.
+    Break(Option<String>, Value),
+    /// `continue`: the rest of the loop body is skipped.
+    Continue(Option<String>),
+    /// `return f(args)` inside a function: the call is made by the caller's call loop
+    /// once the current call has ended, so tail recursion does not nest.
+    TailCall(Value, Vec<Value>, Span),
 }
 
 impl ControlFlow {
     pub fn unwrap(self) -> Value {
         match self {
-            ControlFlow::Continue(value) | ControlFlow::Return(value) => value,
-            ControlFlow::Break => Value::Null,
+            ControlFlow::Normal(value) | ControlFlow::Return(value) | ControlFlow::Break(_, value) => value,
+            ControlFlow::Continue(_) => Value::Null,
+            ControlFlow::TailCall(..) => unreachable!("tail call outside of a function"),
         }
     }
 }
@@ -19,9 +29,31 @@ impl ControlFlow {
 impl From<ControlFlow> for Value {
     fn from(value: ControlFlow) -> Self {
         match value {
-            ControlFlow::Continue(v) => v,
+            ControlFlow::Normal(v) => v,
             ControlFlow::Return(v) => v,
-            ControlFlow::Break => Value::Null,
+            ControlFlow::Break(_, v) => v,
+            ControlFlow::Continue(_) => Value::Null,
+            ControlFlow::TailCall(..) => unreachable!("tail call outside of a function"),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Whether a `break` or `continue` naming `target` is meant for a loop with `label`. One
+/// without a label is meant for the innermost loop.
+pub fn targets(target: &Option<String>, label: &Option<String>) -> bool {
+    target.is_none() || target == label
+}
+
+/// Unwraps the value of a `ControlFlow::Normal`. Any other flow — a `return`, `break` or
+/// `continue` reached while evaluating a subexpression — is returned from the enclosing
+/// function as-is, so it keeps unwinding to the loop or call that handles it.
+macro_rules! value {
+    ($flow:expr) => {
+        match $flow {
+            $crate::control_flow::ControlFlow::Normal(value) => value,
+            flow => return Ok(flow),
+        }
+    };
+}
+
+pub(crate) use value;
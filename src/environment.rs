@@ -1,68 +1,97 @@
-use std::collections::HashMap;
-use serde::{Serialize, Deserialize};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
 
+use crate::ast_node::Slot;
 use crate::value::Value;
+use crate::error::LimError;
 
-#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+/// The variables of one runtime scope, indexed by the slots the resolver gave them. Names
+/// are only passed in to word error messages.
+#[derive(Debug, Default)]
 pub struct Environment {
-    pub values: HashMap<String, Value>,
-    pub parent: Option<Box<Environment>>,
+    /// `None` is a global that has been declared but not yet defined.
+    pub values: Vec<Option<Value>>,
+    pub parent: Option<Rc<RefCell<Environment>>>,
 }
 
 impl Environment {
     pub fn new() -> Self {
-        let env = Self {
-            values: HashMap::new(),
+        Self {
+            values: Vec::new(),
             parent: None,
-        };
-        /*
-        env.define("printf".to_string(), 
-            Value::Function(
-                Function::BuiltIn { func: |args| {
-                        if let Value::String(format) = &args[0] {
-                            let formatted = format_string(format, &args[1..])?;
-                            print!("{}", formatted);
-                            Ok(Value::Null)
-                        } else {
-                            Err(format!("The first argument must be a string, actually found: {}", args[0]))
-                        }
-                    }
-                }
-            )
-        );*/
+        }
+    }
 
-        env
+    /// An empty scope nested inside `parent`.
+    pub fn child(parent: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            values: Vec::new(),
+            parent: Some(parent),
+        }
     }
 
-    pub fn get(&self, name: &str) -> Result<Value, String> {
-        if let Some(value) = self.values.get(name) {
-            Ok(value.clone())
-        } else if let Some(parent) = &self.parent {
-            parent.get(name)
-        } else {
-            Err(format!("Undefined variable: {}", name))
+    pub fn get(&self, name: &str, slot: Slot) -> Result<Value, LimError> {
+        if slot.depth > 0 {
+            return match &self.parent {
+                Some(parent) => parent.borrow().get(name, Slot { depth: slot.depth - 1, ..slot }),
+                None => Err(LimError::name(format!("Undefined variable: {}", name))),
+            };
+        }
+
+        match self.values.get(slot.index) {
+            Some(Some(value)) => Ok(value.clone()),
+            _ => Err(LimError::name(format!("Undefined variable: {}", name))),
         }
     }
 
-    pub fn set(&mut self, name: String, value: Value) -> Result<(), String> {
-        if self.values.contains_key(&name) {
-            self.values.insert(name, value);
+    pub fn set(&mut self, name: &str, slot: Slot, value: Value) -> Result<(), LimError> {
+        self.update(name, slot, |current| {
+            *current = value;
             Ok(())
-        } else if let Some(parent) = self.parent.as_mut() {
-            parent.set(name, value)
-        } else {
-            Err(format!("Variable {} not declared.", name))
+        })
+    }
+
+    /// Changes the value of a variable in place, such as one item of a vector.
+    pub fn update<T>(&mut self, name: &str, slot: Slot, f: impl FnOnce(&mut Value) -> Result<T, LimError>) -> Result<T, LimError> {
+        if slot.depth > 0 {
+            return match &self.parent {
+                Some(parent) => parent.borrow_mut().update(name, Slot { depth: slot.depth - 1, ..slot }, f),
+                None => Err(LimError::name(format!("Variable {} not declared.", name))),
+            };
+        }
+
+        match self.values.get_mut(slot.index) {
+            Some(Some(current)) => f(current),
+            _ => Err(LimError::name(format!("Variable {} not declared.", name))),
         }
     }
 
-    pub fn define(&mut self, name: String, value: Value) -> Result<(), String>{
-        if !self.values.contains_key(&name) {
-            self.values.insert(name, value);
-            Ok(())
-        } else {
-            Err(format!("Variable {} have been declared!", name))
+    pub fn define(&mut self, index: usize, value: Value) {
+        if index >= self.values.len() {
+            self.values.resize(index + 1, None);
         }
-        
+        self.values[index] = Some(value);
+    }
+}
+
+/// The scope a function was created in. It is shared with the code that created the
+/// function, not copied, so the function sees later assignments to the variables it uses.
+///
+/// A named function's scope contains the function itself, so closures compare by identity
+/// and are not printed. They are not serialized either: a built program only ever contains
+/// plain literals, and a deserialized closure is an empty scope.
+#[derive(Clone, Default)]
+pub struct Closure(pub Rc<RefCell<Environment>>);
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
     }
 }
 
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closure")
+    }
+}
@@ -0,0 +1,208 @@
+use std::error::Error;
+use std::fmt;
+use serde::{Serialize, Deserialize};
+
+use crate::span::Span;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ErrorKind {
+    Lex,
+    Parse,
+    Name,
+    Type,
+    Arity,
+    Index,
+    Division,
+    Overflow,
+    Recursion,
+    Match,
+    Io,
+    /// A built program that cannot be read back or written out.
+    Format,
+    /// The system could not provide what running needs, such as the interpreter's stack.
+    Resource,
+    Thrown,
+    Runtime,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Lex => "lex",
+            ErrorKind::Parse => "parse",
+            ErrorKind::Name => "name",
+            ErrorKind::Type => "type",
+            ErrorKind::Arity => "arity",
+            ErrorKind::Index => "index",
+            ErrorKind::Division => "division",
+            ErrorKind::Overflow => "overflow",
+            ErrorKind::Recursion => "recursion",
+            ErrorKind::Match => "match",
+            ErrorKind::Io => "io",
+            ErrorKind::Format => "format",
+            ErrorKind::Resource => "resource",
+            ErrorKind::Thrown => "thrown",
+            ErrorKind::Runtime => "runtime",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An error raised anywhere between reading the source and running it.
+///
+/// `span` is filled in by the innermost layer that knows where the error happened;
+/// layers without source information (such as `Environment`) leave it empty and the
+/// interpreter attaches the location of the node being evaluated.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LimError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Option<Span>,
+    pub cause: Option<Box<LimError>>,
+}
+
+impl LimError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            span: None,
+            cause: None,
+        }
+    }
+
+    pub fn lex(message: impl Into<String>, span: Span) -> Self {
+        Self::new(ErrorKind::Lex, message).at(span)
+    }
+
+    pub fn parse(message: impl Into<String>, span: Span) -> Self {
+        Self::new(ErrorKind::Parse, message).at(span)
+    }
+
+    pub fn name(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Name, message)
+    }
+
+    pub fn type_error(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Type, message)
+    }
+
+    pub fn arity(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Arity, message)
+    }
+
+    pub fn index(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Index, message)
+    }
+
+    pub fn division(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Division, message)
+    }
+
+    pub fn overflow(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Overflow, message)
+    }
+
+    /// Calling the function `name` would nest more than `limit` calls.
+    pub fn stack_overflow(name: Option<&str>, limit: usize) -> Self {
+        let function = name.map_or("an anonymous function".to_string(), |name| format!("function {}", name));
+        Self::new(ErrorKind::Recursion, format!("Stack overflow in {}: more than {} nested calls", function, limit))
+    }
+
+    /// Like `stack_overflow`, for calls that ran out of native stack before the limit.
+    pub fn stack_exhausted(name: Option<&str>, depth: usize) -> Self {
+        let function = name.map_or("an anonymous function".to_string(), |name| format!("function {}", name));
+        Self::new(ErrorKind::Recursion, format!("Stack overflow in {}: out of stack after {} nested calls", function, depth))
+    }
+
+    /// No arm of a `match` matched `value`.
+    pub fn no_match(value: impl fmt::Display) -> Self {
+        Self::new(ErrorKind::Match, format!("No match arm matches the value: {}", value))
+    }
+
+    pub fn mismatch(value: impl fmt::Display) -> Self {
+        Self::new(ErrorKind::Match, format!("The value does not match the pattern: {}", value))
+    }
+
+    pub fn thrown(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Thrown, message)
+    }
+
+    pub fn runtime(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Runtime, message)
+    }
+
+    /// Attaches a location unless a more precise one is already known.
+    pub fn at(mut self, span: Span) -> Self {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    pub fn caused_by(mut self, cause: LimError) -> Self {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+
+    /// Renders the error together with the offending source line and a caret under it.
+    pub fn report(&self, source: &str) -> String {
+        let mut result = self.to_string();
+
+        if let Some(span) = self.span {
+            if let Some(line) = source.lines().nth(span.line.saturating_sub(1)) {
+                let gutter = span.line.to_string();
+                let width = source[span.start.min(source.len())..span.end.min(source.len())]
+                    .lines()
+                    .next()
+                    .map(|text| text.chars().count())
+                    .unwrap_or(0)
+                    .max(1);
+
+                result.push_str(&format!("\n {} | {}", gutter, line));
+                result.push_str(&format!(
+                    "\n {} | {}{}",
+                    " ".repeat(gutter.len()),
+                    " ".repeat(span.column.saturating_sub(1)),
+                    "^".repeat(width),
+                ));
+            }
+        }
+
+        let mut cause = self.cause.as_deref();
+        while let Some(error) = cause {
+            result.push_str(&format!("\ncaused by: {}", error));
+            cause = error.cause.as_deref();
+        }
+
+        result
+    }
+}
+
+impl fmt::Display for LimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error: {}", self.kind, self.message)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}", span)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for LimError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.cause.as_deref().map(|cause| cause as &(dyn Error + 'static))
+    }
+}
+
+impl From<std::io::Error> for LimError {
+    fn from(error: std::io::Error) -> Self {
+        LimError::new(ErrorKind::Io, error.to_string())
+    }
+}
+
+impl From<bincode::Error> for LimError {
+    fn from(error: bincode::Error) -> Self {
+        LimError::new(ErrorKind::Format, error.to_string())
+    }
+}
@@ -1,352 +1,529 @@
-use std::collections::HashMap;
-use serde::{Serialize, Deserialize};
+use std::cell::RefCell;
+use std::rc::Rc;
 
 use crate::debug;
 use crate::builtin::initialization;
 use crate::parser::Parser;
 use crate::token::Token;
 use crate::value::Value;
-use crate::control_flow::ControlFlow;
+use crate::control_flow::{ControlFlow, targets, value};
 use crate::builtin::hole_func;
-use crate::ast_node::{ASTNode, AstRef};
-use crate::environment::Environment;
+use crate::ast_node::{ASTNode, ASTKind, AstRef, MatchArm, Slot};
+use crate::environment::{Closure, Environment};
+use crate::iteration::Iteration;
+use crate::resolver::{Resolver, resolved};
+use crate::optimizer::Optimizer;
+use crate::span::Span;
+use crate::error::LimError;
+
+/// How many calls may be nested by default, in both backends.
+pub const MAX_DEPTH: usize = 1000;
+
+/// Native stack budgeted per nested Lim call. A call's share also covers the nodes its
+/// body nests, which `evaluate` recurses into; unoptimized builds use far larger frames.
+pub const CALL_STACK: usize = if cfg!(debug_assertions) { 256 << 10 } else { 48 << 10 };
+/// Native stack for everything but the calls: parsing, resolving and the top level.
+pub const BASE_STACK: usize = 16 << 20;
+
+/// The native stack a thread running the interpreter needs for `max_depth` nested calls.
+pub fn stack_size(max_depth: usize) -> usize {
+    max_depth.saturating_mul(CALL_STACK).saturating_add(BASE_STACK)
+}
+
+/// The address of the caller's stack frame, to measure how much native stack is in use.
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug)]
 pub struct Interpreter {
-    environment: Box<Environment>,
+    environment: Rc<RefCell<Environment>>,
+    resolver: Resolver,
+    optimizer: Optimizer,
+    /// The number of calls in progress. Each one nests on the native stack.
+    depth: usize,
+    max_depth: usize,
+    /// Where the native stack stood when the running program started.
+    stack_base: usize,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
         Self {
-            environment: Box::new(Environment::new()),
+            environment: Rc::new(RefCell::new(Environment::new())),
+            resolver: Resolver::new(),
+            optimizer: Optimizer::new(),
+            depth: 0,
+            max_depth: MAX_DEPTH,
+            stack_base: 0,
         }
     }
 
-    pub fn init(&mut self) -> Result<(), String> {
-        initialization(&mut self.environment)?;
+    pub fn init(&mut self) -> Result<(), LimError> {
+        initialization(&mut self.resolver, &mut self.environment.borrow_mut())?;
         Ok(())
     }
 
+    /// Lets `let` and `fn` replace a binding already declared in the same scope, so that
+    /// lines can be entered again in the REPL. Later lines may then also assign to globals,
+    /// so functions bound to them are no longer inlined.
+    pub fn set_redeclare(&mut self, redeclare: bool) {
+        self.resolver.redeclare = redeclare;
+        self.optimizer.globals = !redeclare;
+    }
+
+    /// Sets the optimizer level, from 0 (off) to 2; see `Optimizer`.
+    pub fn set_optimization(&mut self, level: u8) {
+        self.optimizer.level = level;
+    }
+
+    /// Limits how many calls may be nested; a call beyond that is a recursion error, as is
+    /// one that would use more native stack than `stack_size` gives that many calls.
+    /// The native stack must be at least `stack_size(max_depth)`.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn interpret(&mut self, text: String) -> Result<Value, LimError> {
+        let program = self.prepare(text)?;
+        self.run(&program)
+    }
 
-    pub fn interpret(&mut self, text: String) -> Result<Value, String> {
-        let ast = Parser::new(text)?.parse()?;
+    /// Parses, resolves and optimizes source text against this interpreter's globals.
+    pub fn prepare(&mut self, text: String) -> Result<ASTNode, LimError> {
+        let mut ast = Parser::new(text)?.parse()?;
         debug!("ast: {:?}", ast);
-        Ok(self.evaluate(&ast)?.unwrap())
+        self.resolver.resolve(&mut ast)?;
+        Ok(self.optimizer.optimize(ast))
     }
 
-    pub fn evaluate(&mut self, node: &ASTNode) -> Result<ControlFlow, String> {
-        let result = match node {
-            ASTNode::Block { statements } => {
-                let mut result = ControlFlow::Continue(Value::Null);
+    /// Resolves and runs a parsed program, such as a built one.
+    pub fn execute(&mut self, mut program: ASTNode) -> Result<Value, LimError> {
+        self.resolver.resolve(&mut program)?;
+        self.run(&program)
+    }
 
-                for statement in *statements.clone() {
-                    result = self.evaluate(&statement)?;
-                    if let ControlFlow::Return(_) = result {
-                        break;
-                    }
-                }
+    /// Runs a resolved program. Its top-level statements share the interpreter's global
+    /// scope, so definitions stay visible to later programs (and REPL lines).
+    fn run(&mut self, program: &ASTNode) -> Result<Value, LimError> {
+        self.stack_base = stack_address();
+        let result = match &program.kind {
+            ASTKind::Block { statements } => self.evaluate_statements(statements)?,
+            _ => self.evaluate(program)?,
+        };
+
+        Ok(result.unwrap())
+    }
+
+    fn evaluate_statements(&mut self, statements: &[ASTNode]) -> Result<ControlFlow, LimError> {
+        let mut result = Value::Null;
+
+        // Functions declared in a block are defined before it runs, so they can be called
+        // from anywhere in it, including from each other.
+        for statement in statements {
+            if let ASTKind::FunctionDeclaration { name, params, body, slot } = &statement.kind {
+                self.declare(name, slot, params, body).map_err(|e| e.at(statement.span))?;
+            }
+        }
+
+        for statement in statements {
+            if let ASTKind::FunctionDeclaration { .. } = statement.kind {
+                result = Value::Null;
+            } else {
+                result = value!(self.evaluate(statement)?);
+            }
+        }
+
+        Ok(ControlFlow::Normal(result))
+    }
+
+    /// Evaluates `node` in a new scope nested in the current one. A block's statements
+    /// run directly in that scope.
+    fn evaluate_scoped(&mut self, node: &ASTNode) -> Result<ControlFlow, LimError> {
+        let parent = self.environment.clone();
+        self.environment = Rc::new(RefCell::new(Environment::child(parent.clone())));
+
+        let result = match &node.kind {
+            ASTKind::Block { statements } => self.evaluate_statements(statements),
+            _ => self.evaluate(node),
+        };
+
+        self.environment = parent;
+        result
+    }
+
+    fn define(&mut self, name: &str, slot: &Option<Slot>, value: Value) -> Result<(), LimError> {
+        let slot = resolved(slot, name)?;
+        self.environment.borrow_mut().define(slot.index, value);
+        Ok(())
+    }
 
-                result
+    /// Defines a named function closing over the current scope, which is also where its
+    /// name lives, so the function can refer to itself.
+    fn declare(&mut self, name: &str, slot: &Option<Slot>, params: &[String], body: &ASTNode) -> Result<(), LimError> {
+        let function = self.closure(Some(name), params, body);
+        self.define(name, slot, function)
+    }
+
+    fn closure(&self, name: Option<&str>, params: &[String], body: &ASTNode) -> Value {
+        Value::Function {
+            name: name.map(str::to_string),
+            params: params.to_vec(),
+            body: Box::new(body.clone()),
+            closure: Closure(self.environment.clone()),
+        }
+    }
+
+    /// Evaluates a node. It nests several times for every call the program makes, so the
+    /// larger cases live in their own methods to keep its stack frame small.
+    fn evaluate(&mut self, node: &ASTNode) -> Result<ControlFlow, LimError> {
+        let result = match &node.kind {
+            ASTKind::Block { .. } => {
+                value!(self.evaluate_scoped(node)?)
             },
 
 
-            ASTNode::Let { ast } => {
-                match *ast.clone() {
-                    ASTNode::Identifier(name) => self.environment.define(*name, Value::Null)?,
-                    ASTNode::Assignment { name, value } => {
-                        let value = self.evaluate_expression(&value)?;
-                        self.environment.define(*name, value)?
-                    },
-                    _ => return Err(format!("Cannot binding this: {:?}", ast)),
-                }
-                
-                ControlFlow::Continue(Value::Null)
+            ASTKind::Let { ast } => return self.evaluate_let(ast),
+
+            ASTKind::FunctionDeclaration { name, params, body, slot } => {
+                self.declare(name, slot, params, body).map_err(|e| e.at(node.span))?;
+                Value::Null
             },
 
-            ASTNode::Conditional { condition, true_branch, false_branch } => {
-                let condition_value = self.evaluate(condition)?;
-                if let Value::Boolean(true) = condition_value.unwrap() {
-                    self.evaluate(true_branch)?
+            ASTKind::Conditional { condition, true_branch, false_branch } => {
+                if let Value::Boolean(true) = value!(self.evaluate(condition)?) {
+                    value!(self.evaluate_scoped(true_branch)?)
+                } else if let Some(false_branch) = false_branch {
+                    value!(self.evaluate_scoped(false_branch)?)
                 } else {
-                    if let Some(false_branch) = false_branch {
-                        self.evaluate(false_branch)?
-                    } else {
-                        ControlFlow::Continue(Value::Null)
-                    }
+                    Value::Null
                 }
             },
 
-            ASTNode::Loop { condition, body } => {
-                let mut result = ControlFlow::Continue(Value::Null);
+            ASTKind::Loop { condition, body, label } => return self.evaluate_while(condition, body, label),
 
-                while let Value::Boolean(true) = self.evaluate_expression(condition)? {
-                    result = self.evaluate(body)?;
-                    match result {
-                        ControlFlow::Return(_) => return Ok(result),
-                        ControlFlow::Continue(_) => (),
-                        ControlFlow::Break => break,
-                    }
-                }
+            ASTKind::For { variable, iterable, body, slot, label } => return self.evaluate_for(node, variable, slot, iterable, body, label),
 
-                result
+            ASTKind::Break { label, value } => {
+                let value = match value {
+                    Some(value) => value!(self.evaluate(value)?),
+                    None => Value::Null,
+                };
+                return Ok(ControlFlow::Break(label.clone(), value));
             },
 
-            ASTNode::Break => {
-                ControlFlow::Break
-            }
-
-            ASTNode::Return(expr) => {
-                let value = self.evaluate_expression(expr)?;
-                ControlFlow::Return(value)
+            ASTKind::Continue { label } => {
+                return Ok(ControlFlow::Continue(label.clone()));
             },
 
-            _ => {
-                ControlFlow::Continue(self.evaluate_expression(node)?)
-            },
-        };
-        
-        Ok(result)
-    }
+            ASTKind::Return(expr) => return self.evaluate_return(expr),
 
-    
-    fn evaluate_expression(&mut self, node: &ASTNode) -> Result<Value, String> {
-        let result = match node {
-            ASTNode::BinaryOperation { operator, left, right } => {
-                let left = self.evaluate_expression(&left)?;
-                let right = self.evaluate_expression(&right)?;
-
-                let result = match (left.clone(), right.clone()) {
-                    (Value::Number(left), Value::Number(right)) => {
-                        match operator {
-                            Token::Plus => left + right,
-                            Token::Minus => left - right,
-                            Token::Mul => left * right,
-                            Token::Div => if right == 0.0 { return Err("Division by zero!".to_string()) } else { left / right },
-                            Token::Mod => if right == 0.0 { return Err("Modulo by zero".to_string()) } else { left % right },
-
-                            _ => {
-                                return Err(format!("Invalid operator for binary operation: {:?}", operator))
-                            },
-                        }
-                    },
-                    _ => {
-                        return Err(format!("Invalid operands for binary operation: {:?} {:?}", left, right))
-                    }
-                };
-                
-                Value::Number(result)
-            },
+            ASTKind::Match { scrutinee, arms } => return self.evaluate_match(scrutinee, arms),
 
-            ASTNode::LogicalOperation { operator, left, right } => {
-                let result = match operator {
-                    Token::And => {
-                        if self.evaluate_expression(&left)?.get_boolean()? {
-                            if self.evaluate_expression(&right)?.get_boolean()? {
-                                true
-                            } else {
-                                false
-                            }
-                        } else {
-                            false
-                        }
-                    },
-
-                    Token::Or => {
-                        if self.evaluate_expression(&left)?.get_boolean()? {
-                            true
-                        } else if self.evaluate_expression(&right)?.get_boolean()? {
-                            true
-                        } else {
-                            false
-                        }
-                    },
-
-                    _ => {
-                        let left: Value = self.evaluate_expression(&left)?.into();
-                        let right: Value = self.evaluate_expression(&right)?.into();
-
-                        match operator {
-                            Token::Equal => left.equal(right)?,
-                            Token::UnEqual => !left.equal(right)?,
-
-                            _ => {
-                                let left: f64 = left.to_number()?;
-                                let right: f64 = right.to_number()?;
-
-                                match operator {
-                                    Token::Greater => {
-                                        left > right
-                                    }, 
-                                    Token::Less => {
-                                        left < right
-                                    }, 
-                                    Token::GreaterEqual => {
-                                        left >= right
-                                    }, 
-                                    Token::LessEqual => {
-                                        left <= right
-                                    }, 
-                                    _ => {
-                                        return Err(format!("Invalid operator for binary operation: {:?}", operator))
-                                    }
-                                }
-                                
-                            }
-                        }
-                    },
-                };
+            ASTKind::BinaryOperation { operator, left, right } => {
+                let left = value!(self.evaluate(left)?);
+                let right = value!(self.evaluate(right)?);
 
-                Value::Boolean(result)
+                left.arithmetic(operator, &right).map_err(|e| e.at(node.span))?
             },
 
-            ASTNode::UnaryOperation { operator, operand } => {
-                let operand_value = self.evaluate_expression(operand)?;
-
-                match operator {
-                    Token::Plus => {
-                        operand_value
-                    },
-                    Token::Minus => {
-                        if let Value::Number(v) = operand_value {
-                            Value::Number(-v)
-                        } else {
-                            return Err(format!("Invalid operand for unary minus: {:?}", operand_value))
-                        }
-                    },
-                    Token::Not => {
-                        if let Value::Boolean(v) = operand_value {
-                            Value::Boolean(!v)
-                        } else {
-                            return Err(format!("Invalid operand for logical NOT: {:?}", operand_value))
-                        }
-                    },
-                    _ => {
-                        return Err(format!("Invalid operator for unary operation: {:?}", operator))
-                    }
-                }
+            ASTKind::LogicalOperation { operator, left, right } => return self.evaluate_logical(node, operator, left, right),
+
+            ASTKind::UnaryOperation { operator, operand } => {
+                let operand_value = value!(self.evaluate(operand)?);
+                operand_value.unary(operator).map_err(|e| e.at(node.span))?
             },
 
-            ASTNode::Literal(value) => {
+            ASTKind::Literal(value) => {
                 value.clone()
             },
 
-            ASTNode::Identifier(name) => {
-                self.get_variable_value(name)?
+            ASTKind::Interpolation(parts) => {
+                let mut result = String::new();
+
+                for part in parts {
+                    result.push_str(&value!(self.evaluate(part)?).to_string());
+                }
+
+                Value::String(result)
+            },
+
+            ASTKind::Identifier { name, slot } => {
+                let slot = resolved(slot, name).map_err(|e| e.at(node.span))?;
+                self.environment.borrow().get(name, slot).map_err(|e| e.at(node.span))?
             },
 
-            ASTNode::Tuple(tuple) => {
+            ASTKind::Tuple(tuple) => {
                 let mut result: Vec<Value> = vec![];
 
-                for i in *tuple.clone() {
-                    result.push(self.evaluate_expression(&i)?);
+                for i in tuple {
+                    result.push(value!(self.evaluate(i)?));
                 }
 
                 Value::Tuple(result)
             },
 
-            ASTNode::Vector(vector) => {
+            ASTKind::Vector(vector) => {
                 let mut result: Vec<Value> = vec![];
 
-                for i in *vector.clone() {
-                    result.push(self.evaluate_expression(&i)?);
+                for i in vector {
+                    result.push(value!(self.evaluate(i)?));
                 }
 
                 Value::Vector(result)
             },
 
-            ASTNode::Index { expression, index } => {
-                let expression = self.evaluate_expression(expression)?;
-                let index = self.evaluate_expression(index)?;
-
-                match expression {
-                    Value::Tuple(list) | Value::Vector(list) => {
-                        match index {
-                            Value::Number(num) => {
-                                let index = num as usize;
-                                if index < list.len() {
-                                    list[index as usize].clone()
-                                } else {
-                                    return Err(format!("Index out of bounds: the len is {} but the index is {}", list.len(), index));
-                                }
-                                
-                            }, // todo: not true
-                            _ => return Err(format!("This expression cannot be used as an index: {index}")),
-                        }
-                    },
-                    _ => return Err(format!("This expression cannot be indexed: {expression}")),
-                }
+            ASTKind::Index { expression, index } => {
+                let expression = value!(self.evaluate(expression)?);
+                let index = value!(self.evaluate(index)?);
+                expression.index(&index).map_err(|e| e.at(node.span))?
             },
 
-            ASTNode::Assignment { name, value } => {
-                let evaluated_value = self.evaluate_expression(value)?;
-                self.environment.set(*name.clone(), evaluated_value.clone())?;
+            ASTKind::Assignment { name, value, slot } => {
+                let evaluated_value = value!(self.evaluate(value)?);
+                let slot = resolved(slot, name).map_err(|e| e.at(node.span))?;
+                self.environment.borrow_mut().set(name, slot, evaluated_value.clone()).map_err(|e| e.at(node.span))?;
                 evaluated_value
             },
 
-            ASTNode::FunctionDefinition { params, body } => {
-                Value::Function{
-                    params: params.clone(),
-                    body: Box::clone(body),
-                    closure: self.environment.clone(),
-                }
+            ASTKind::IndexAssignment { name, indices, operator, value, slot } => {
+                return self.evaluate_index_assignment(node, name, slot, indices, operator, value);
+            },
+
+            ASTKind::Destructure { pattern, value } => {
+                let value = value!(self.evaluate(value)?);
+                pattern.destructure(&value, &self.environment, false).map_err(|e| e.at(node.span))?;
+                value
+            },
+
+            ASTKind::FunctionDefinition { params, body } => {
+                self.closure(None, params, body)
             },
 
-            ASTNode::FunctionCall { function, arguments } => {
-                if let Some(function) = function {
-                    let result = self.evaluate_function_call(self.get_variable_value(function)?, arguments)?;
-                    result
+            ASTKind::FunctionCall { function, arguments } => {
+                let function = value!(self.evaluate(function)?);
+                return self.evaluate_function_call(function, arguments, node.span);
+            },
+        };
+
+        Ok(ControlFlow::Normal(result))
+    }
+
+
+    fn evaluate_let(&mut self, ast: &ASTNode) -> Result<ControlFlow, LimError> {
+        match &ast.kind {
+            ASTKind::Identifier { name, slot } => self.define(name, slot, Value::Null).map_err(|e| e.at(ast.span))?,
+            ASTKind::Assignment { name, value, slot } => {
+                // `let f = fn ...` names the function after the variable.
+                let value = match &value.kind {
+                    ASTKind::FunctionDefinition { params, body } => self.closure(Some(name), params, body),
+                    _ => value!(self.evaluate(value)?),
+                };
+                self.define(name, slot, value).map_err(|e| e.at(ast.span))?
+            },
+            ASTKind::Destructure { pattern, value } => {
+                let value = value!(self.evaluate(value)?);
+                pattern.destructure(&value, &self.environment, true).map_err(|e| e.at(ast.span))?
+            },
+            _ => return Err(LimError::runtime(format!("Cannot declare this: {:?}", ast.kind)).at(ast.span)),
+        }
+
+        Ok(ControlFlow::Normal(Value::Null))
+    }
+
+    /// Evaluates the indices, then the value, then changes the item in place.
+    fn evaluate_index_assignment(&mut self, node: &ASTNode, name: &str, slot: &Option<Slot>, indices: &[ASTNode], operator: &Option<Token>, value: &ASTNode) -> Result<ControlFlow, LimError> {
+        let mut evaluated = Vec::with_capacity(indices.len());
+        for index in indices {
+            evaluated.push(value!(self.evaluate(index)?));
+        }
+        let value = value!(self.evaluate(value)?);
+
+        let slot = resolved(slot, name).map_err(|e| e.at(node.span))?;
+        let result = self.environment.borrow_mut()
+            .update(name, slot, |target| target.assign_index(&evaluated, operator.as_ref(), value))
+            .map_err(|e| e.at(node.span))?;
+        Ok(ControlFlow::Normal(result))
+    }
+
+    fn evaluate_while(&mut self, condition: &ASTNode, body: &ASTNode, label: &Option<String>) -> Result<ControlFlow, LimError> {
+        let mut result = Value::Null;
+
+        while let Value::Boolean(true) = value!(self.evaluate(condition)?) {
+            // Every iteration gets a fresh scope for the body's bindings.
+            match self.evaluate_scoped(body)? {
+                ControlFlow::Normal(value) => result = value,
+                ControlFlow::Continue(target) if targets(&target, label) => result = Value::Null,
+                ControlFlow::Break(target, value) if targets(&target, label) => {
+                    result = value;
+                    break;
+                },
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(ControlFlow::Normal(result))
+    }
+
+    fn evaluate_return(&mut self, expr: &ASTNode) -> Result<ControlFlow, LimError> {
+        if let (ASTKind::FunctionCall { function, arguments }, true) = (&expr.kind, self.depth > 0) {
+            let function = value!(self.evaluate(function)?);
+            let mut args = Vec::with_capacity(arguments.len());
+            for arg in arguments {
+                args.push(value!(self.evaluate(arg)?));
+            }
+
+            return Ok(match function {
+                Value::Function { .. } => ControlFlow::TailCall(function, args, expr.span),
+                _ => ControlFlow::Return(self.call(function, args, expr.span)?),
+            });
+        }
+
+        let value = value!(self.evaluate(expr)?);
+        Ok(ControlFlow::Return(value))
+    }
+
+    fn evaluate_logical(&mut self, node: &ASTNode, operator: &Token, left_node: &ASTNode, right_node: &ASTNode) -> Result<ControlFlow, LimError> {
+        let left = value!(self.evaluate(left_node)?);
+
+        let result = match operator {
+            Token::And | Token::Or => {
+                let left = left.get_boolean().map_err(|e| e.at(left_node.span))?;
+
+                // Short-circuit: the right operand only runs when it decides the result.
+                if left == (*operator == Token::Or) {
+                    left
                 } else {
-                    return Err(format!("Expected String, found: null"))
+                    value!(self.evaluate(right_node)?).get_boolean().map_err(|e| e.at(right_node.span))?
                 }
-                
             },
 
-            _ => return Err(format!("{:?} is not an expression", node))
+            _ => {
+                let right = value!(self.evaluate(right_node)?);
+                left.relation(operator, &right).map_err(|e| e.at(node.span))?
+            },
         };
 
-        Ok(result)
+        Ok(ControlFlow::Normal(Value::Boolean(result)))
     }
-    
 
-    fn evaluate_function_call<T: AstRef>(&mut self, function: Value, arguments: &[T]) -> Result<Value, String> {
+    fn evaluate_for(&mut self, node: &ASTNode, variable: &str, slot: &Option<Slot>, iterable: &ASTNode, body: &ASTNode, label: &Option<String>) -> Result<ControlFlow, LimError> {
+        let value = value!(self.evaluate(iterable)?);
+        let mut iteration = Iteration::new(value).map_err(|e| e.at(iterable.span))?;
+        let mut result = Value::Null;
+
+        while let Some(item) = iteration.next(|function| self.call(function.clone(), vec![], iterable.span))? {
+            // The variable gets a fresh scope every iteration, around the body's.
+            let parent = self.environment.clone();
+            self.environment = Rc::new(RefCell::new(Environment::child(parent.clone())));
+            let flow = self.define(variable, slot, item).map_err(|e| e.at(node.span)).and_then(|_| self.evaluate_scoped(body));
+            self.environment = parent;
+
+            match flow? {
+                ControlFlow::Normal(value) => result = value,
+                ControlFlow::Continue(target) if targets(&target, label) => result = Value::Null,
+                ControlFlow::Break(target, value) if targets(&target, label) => {
+                    result = value;
+                    break;
+                },
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(ControlFlow::Normal(result))
+    }
+
+    fn evaluate_match(&mut self, scrutinee: &ASTNode, arms: &[MatchArm]) -> Result<ControlFlow, LimError> {
+        let value = value!(self.evaluate(scrutinee)?);
 
-        if let Value::Function { params, body, closure } = function.clone() {
-            if params.len() != arguments.len() {
-                return Err(format!("Function expected {} arguments, but got {}", params.len(), arguments.len()));
+        for arm in arms {
+            let mut bindings = vec![];
+            if !arm.pattern.matches(&value, &mut bindings).map_err(|e| e.at(scrutinee.span))? {
+                continue;
             }
-            
-            let mut new_env = Environment {
-                values: HashMap::new(),
-                parent: Some(closure.clone()),
-            };
-            
-            for (param, arg) in params.iter().zip(arguments) {
-                let arg_value = self.evaluate_expression(arg.as_ast())?;
-                new_env.values.insert(param.clone(), arg_value);
+
+            // The bindings get a scope of their own, around the body's.
+            let parent = self.environment.clone();
+            let mut scope = Environment::child(parent.clone());
+            for (_, slot, value) in bindings {
+                scope.define(slot.index, value);
+            }
+            self.environment = Rc::new(RefCell::new(scope));
+            let flow = self.evaluate_arm(arm);
+            self.environment = parent;
+
+            if let Some(flow) = flow? {
+                return Ok(flow);
+            }
+        }
+
+        Err(LimError::no_match(value).at(scrutinee.span))
+    }
+
+    /// Evaluates the body of an arm whose pattern matched, unless its guard fails.
+    fn evaluate_arm(&mut self, arm: &MatchArm) -> Result<Option<ControlFlow>, LimError> {
+        if let Some(guard) = &arm.guard {
+            match self.evaluate(guard)? {
+                ControlFlow::Normal(Value::Boolean(true)) => (),
+                ControlFlow::Normal(_) => return Ok(None),
+                flow => return Ok(Some(flow)),
             }
-            new_env.define("self".to_string(), function.clone())?;
-            
-            let old_env = std::mem::replace(&mut self.environment, Box::new(new_env));
-            let result = self.evaluate(&body);
-            self.environment = old_env;
-            
-            match result {
-                Ok(c) => Ok(c.unwrap()),
-                Err(e) => Err(e),
-            } 
-        } else if let Value::Hole(id) = function {
-            let args: Vec<Value> = arguments.iter()
-                .map(|arg| self.evaluate_expression(arg.as_ast()))
-                .collect::<Result<Vec<Value>, String>>()?;
-            return hole_func(id, args);
-        } else {
-            Err("Attempted to call a non-function value".to_string())
         }
+
+        self.evaluate_scoped(&arm.body).map(Some)
     }
 
+    fn evaluate_function_call<T: AstRef>(&mut self, function: Value, arguments: &[T], span: Span) -> Result<ControlFlow, LimError> {
+        let mut args = Vec::with_capacity(arguments.len());
+        for arg in arguments {
+            args.push(value!(self.evaluate(arg.as_ast())?));
+        }
 
-    fn get_variable_value(&self, name: &str) -> Result<Value, String> {
-        self.environment.get(name)
+        Ok(ControlFlow::Normal(self.call(function, args, span)?))
+    }
+
+    fn call(&mut self, mut function: Value, mut args: Vec<Value>, mut span: Span) -> Result<Value, LimError> {
+        // Each turn makes one call; a tail call in its body comes back here for the next.
+        loop {
+            let Value::Function { name, params, body, closure } = &function else {
+                return match function {
+                    Value::Hole(id) => hole_func(id, args).map_err(|e| e.at(span)),
+                    _ => Err(LimError::type_error(format!("Attempted to call a non-function value: {}", function)).at(span)),
+                };
+            };
+
+            if params.len() != args.len() {
+                return Err(LimError::arity(format!("Function expected {} arguments, but got {}", params.len(), args.len())).at(span));
+            }
+            if self.depth >= self.max_depth {
+                return Err(LimError::stack_overflow(name.as_deref(), self.max_depth).at(span));
+            }
+            // Deeply nested syntax around the calls uses more stack than their count shows.
+            if self.stack_base.abs_diff(stack_address()) > self.max_depth.saturating_mul(CALL_STACK) {
+                return Err(LimError::stack_exhausted(name.as_deref(), self.depth).at(span));
+            }
+
+            // The resolver numbers the parameters first, then `self`.
+            let new_env = Environment {
+                values: args.into_iter().map(Some).chain([Some(function.clone())]).collect(),
+                parent: Some(closure.0.clone()),
+            };
+
+            let old_env = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(new_env)));
+            self.depth += 1;
+            let result = self.evaluate(body);
+            self.depth -= 1;
+            self.environment = old_env;
+
+            match result? {
+                ControlFlow::TailCall(next, next_args, next_span) => {
+                    (function, args, span) = (next, next_args, next_span);
+                },
+                // A `return` ends here; a stray `break` or `continue` does not escape the function either.
+                flow => return Ok(flow.unwrap()),
+            }
+        }
     }
 }
@@ -0,0 +1,53 @@
+use std::vec;
+
+use crate::error::LimError;
+use crate::value::Value;
+
+/// The protocol `for` iterates by, shared by both backends. A value is iterable if it is
+///
+/// - a vector or a tuple, which yields its items;
+/// - a string, which yields its characters as one-character strings;
+/// - a range, which yields its integers in order;
+/// - a function, which is called with no arguments for each item until it returns `null`.
+///
+/// Functions make everything else iterable: a closure keeping its position in a variable
+/// it captured, or a builtin returning the next item, can be handed to `for` as it is.
+pub enum Iteration {
+    Items(vec::IntoIter<Value>),
+    Range { next: i64, end: i64 },
+    Function(Value),
+}
+
+impl Iteration {
+    pub fn new(value: Value) -> Result<Self, LimError> {
+        match value {
+            Value::Vector(items) | Value::Tuple(items) => Ok(Iteration::Items(items.into_iter())),
+            Value::String(string) => {
+                let characters: Vec<Value> = string.chars().map(|c| Value::String(c.to_string())).collect();
+                Ok(Iteration::Items(characters.into_iter()))
+            },
+            Value::Range { start, end } => Ok(Iteration::Range { next: start, end }),
+            Value::Function { .. } | Value::Compiled { .. } | Value::Hole(_) => Ok(Iteration::Function(value)),
+            _ => Err(LimError::type_error(format!("This value cannot be iterated: {}", value))),
+        }
+    }
+
+    /// The next item, or `None` once there are no more. `call` makes the call for a
+    /// function, in whichever backend is running.
+    pub fn next(&mut self, call: impl FnOnce(&Value) -> Result<Value, LimError>) -> Result<Option<Value>, LimError> {
+        match self {
+            Iteration::Items(items) => Ok(items.next()),
+            Iteration::Range { next, end } => {
+                if next >= end {
+                    return Ok(None);
+                }
+                *next += 1;
+                Ok(Some(Value::Int(*next - 1)))
+            },
+            Iteration::Function(function) => match call(function)? {
+                Value::Null => Ok(None),
+                item => Ok(Some(item)),
+            },
+        }
+    }
+}
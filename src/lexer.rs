@@ -1,210 +1,577 @@
-use crate::Token;
+use crate::token::{Token, Segment};
+use crate::span::{Span, Spanned};
+use crate::error::LimError;
+use crate::value::MAX_BITS;
 
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Pow, ToPrimitive};
 use serde::{Serialize, Deserialize};
-use log::{debug, error};
+use log::debug;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Lexer {
-	pub tokens: Vec<Token>,
+	chars: Vec<char>,
 	pos: usize,
+	offset: usize,
+	line: usize,
+	column: usize,
+	errors: Vec<LimError>,
 }
 
 impl Lexer {
-	pub fn new() -> Self {
+	pub fn new(text: &str) -> Self {
 		Self {
-			tokens: Vec::new(),
+			chars: text.chars().collect(),
 			pos: 0,
+			offset: 0,
+			line: 1,
+			column: 1,
+			errors: Vec::new(),
 		}
 	}
 
-	fn error(&self) -> ! {
-		error!("Invalid character!");
-		panic!("Invalid character!");
+	/// Records an error and lets the caller carry on, so one pass reports every problem.
+	fn error(&mut self, message: impl Into<String>, span: Span) {
+		self.errors.push(LimError::lex(message, span));
 	}
 
-	pub fn tokenize(text: String) -> Result<Vec<Token>, String> {
-		let lexer = Lexer::new();
+	fn peek(&self) -> Option<char> {
+		self.chars.get(self.pos).copied()
+	}
+
+	fn peek_next(&self) -> Option<char> {
+		self.peek_at(1)
+	}
 
+	fn peek_at(&self, n: usize) -> Option<char> {
+		self.chars.get(self.pos + n).copied()
+	}
+
+	fn advance(&mut self) -> Option<char> {
+		let ch = self.peek()?;
+		self.pos += 1;
+		self.offset += ch.len_utf8();
+		if ch == '\n' {
+			self.line += 1;
+			self.column = 1;
+		} else {
+			self.column += 1;
+		}
+		Some(ch)
+	}
+
+	/// An empty span at the current position, to be extended with `span_from`.
+	fn mark(&self) -> Span {
+		Span::new(self.offset, self.offset, self.line, self.column)
+	}
+
+	fn span_from(&self, start: Span) -> Span {
+		Span::new(start.start, self.offset, start.line, start.column)
+	}
+
+	/// Tokenizes `text`, failing with the first error found.
+	pub fn tokenize(text: String) -> Result<Vec<Spanned<Token>>, LimError> {
+		let (tokens, mut errors) = Lexer::tokenize_all(&text);
+
+		if errors.is_empty() {
+			Ok(tokens)
+		} else {
+			Err(errors.remove(0))
+		}
+	}
+
+	/// Tokenizes `text`, skipping over invalid input and collecting every error on the way.
+	pub fn tokenize_all(text: &str) -> (Vec<Spanned<Token>>, Vec<LimError>) {
+		let mut lexer = Lexer::new(text);
 		let mut tokens = Vec::new();
-		let mut current_char = text.chars().peekable();
 
-		while let Some(&ch) = current_char.peek() {
+		if lexer.peek() == Some('#') && lexer.peek_next() == Some('!') {
+			lexer.line_comment();
+		}
+
+		while let Some(token) = lexer.next_token() {
+			tokens.push(token);
+		}
+
+		tokens.push(Spanned::new(Token::Eof, lexer.mark()));
+		(tokens, lexer.errors)
+	}
+
+	/// Skips whitespace and comments and lexes the next token, or returns `None` at the end of input.
+	fn next_token(&mut self) -> Option<Spanned<Token>> {
+		while let Some(ch) = self.peek() {
 			if ch.is_whitespace() {
-				current_char.next();
+				self.advance();
 				continue;
 			}
 
-			match ch {
-				'"' => {
-					tokens.push(Token::String(lexer.string(&mut current_char)?.into()));
+			if ch == '/' {
+				match self.peek_next() {
+					Some('/') => {
+						self.line_comment();
+						continue;
+					},
+					Some('*') => {
+						self.block_comment();
+						continue;
+					},
+					_ => (),
 				}
-				ch if ch.is_digit(10) || ch == '.' => {
-					tokens.push(Token::Float(lexer.number(&mut current_char)));
+			}
+
+			let start = self.mark();
+			let token = match ch {
+				'"' if self.peek_next() == Some('"') && self.peek_at(2) == Some('"') => Token::String(self.multiline_string()),
+				'"' => self.string(),
+				'r' if self.is_raw_string() => Token::String(self.raw_string()),
+				'\'' if self.peek_next().is_some_and(|ch| ch.is_alphabetic() || ch == '_') => {
+					self.advance();
+					Token::Label(self.identifier())
+				},
+				'.' if self.peek_next() == Some('.') && self.peek_at(2) == Some('.') => {
+					self.advance();
+					self.advance();
+					self.advance();
+					Token::Ellipsis
 				},
+				'.' if self.peek_next() == Some('.') => {
+					self.advance();
+					self.advance();
+					self.either('=', Token::DotDotEq, Token::DotDot)
+				},
+				ch if ch.is_ascii_digit() || ch == '.' => self.number(),
 				ch if ch.is_alphabetic() || ch == '_' => {
-					let id = lexer.identifier(&mut current_char);
+					let id = self.identifier();
 					match id.as_str() {
-						"fn" => tokens.push(Token::FN),
-						"return" => tokens.push(Token::Return),
+						"fn" => Token::FN,
+						"return" => Token::Return,
 
-						"true" => tokens.push(Token::True),
-						"false" => tokens.push(Token::False),
-						"null" => tokens.push(Token::Null),
+						"true" => Token::True,
+						"false" => Token::False,
+						"null" => Token::Null,
 
-						"let" => tokens.push(Token::Let),
-						"if" => tokens.push(Token::If),
-						"else" => tokens.push(Token::Else),
-						"break" => tokens.push(Token::Break),
+						"let" => Token::Let,
+						"if" => Token::If,
+						"else" => Token::Else,
+						"break" => Token::Break,
+						"continue" => Token::Continue,
 
-						"while" => tokens.push(Token::While),
-						_ => tokens.push(Token::Identifier(id.into())),
+						"while" => Token::While,
+						"loop" => Token::Loop,
+						"for" => Token::For,
+						"in" => Token::In,
+						"match" => Token::Match,
+						_ => Token::Identifier(id),
 					}
 				},
-				'+' => { tokens.push(Token::Plus); current_char.next(); },
-				'-' => { tokens.push(Token::Minus); current_char.next(); },
-				'*' => { tokens.push(Token::Mul); current_char.next(); },
-				'/' => { tokens.push(Token::Div); current_char.next(); },
-				'%' => { tokens.push(Token::Mod); current_char.next(); },
-				',' => { tokens.push(Token::Comma); current_char.next(); },
-				'(' => { tokens.push(Token::LParen); current_char.next(); },
-				')' => { tokens.push(Token::RParen); current_char.next(); },
-				'[' => { tokens.push(Token::LBracket); current_char.next(); },
-				']' => { tokens.push(Token::RBracket); current_char.next(); },
-				'{' => { tokens.push(Token::LBrace); current_char.next(); },
-				'}' => { tokens.push(Token::RBrace); current_char.next(); },
-				';' => { tokens.push(Token::Semicolon); current_char.next(); },
-				'?' => { tokens.push(Token::Question); current_char.next(); },
-				':' => { tokens.push(Token::Colon); current_char.next(); },
-				'&' => {
-					current_char.next();
-					if current_char.peek() == Some(&'&') {
-						tokens.push(Token::And);
-						current_char.next();
-					} else {
-						lexer.error();
+				_ => {
+					self.advance();
+					let token = match ch {
+						'+' => Some(self.either('=', Token::PlusAssign, Token::Plus)),
+						'-' => Some(self.either('=', Token::MinusAssign, Token::Minus)),
+						'*' if self.peek() == Some('*') => {
+							self.advance();
+							Some(Token::Pow)
+						},
+						'*' => Some(self.either('=', Token::MulAssign, Token::Mul)),
+						'/' => Some(self.either('=', Token::DivAssign, Token::Div)),
+						'%' => Some(self.either('=', Token::ModAssign, Token::Mod)),
+						',' => Some(Token::Comma),
+						'(' => Some(Token::LParen),
+						')' => Some(Token::RParen),
+						'[' => Some(Token::LBracket),
+						']' => Some(Token::RBracket),
+						'{' => Some(Token::LBrace),
+						'}' => Some(Token::RBrace),
+						';' => Some(Token::Semicolon),
+						'?' => Some(Token::Question),
+						':' => Some(Token::Colon),
+						'&' if self.peek() == Some('&') => {
+							self.advance();
+							Some(Token::And)
+						},
+						'|' if self.peek() == Some('|') => {
+							self.advance();
+							Some(Token::Or)
+						},
+						'&' => Some(self.either('=', Token::BitAndAssign, Token::BitAnd)),
+						'|' => Some(self.either('=', Token::BitOrAssign, Token::BitOr)),
+						'^' => Some(self.either('=', Token::BitXorAssign, Token::BitXor)),
+						'~' => Some(Token::BitNot),
+						'>' if self.peek() == Some('>') => {
+							self.advance();
+							Some(self.either('=', Token::ShrAssign, Token::Shr))
+						},
+						'<' if self.peek() == Some('<') => {
+							self.advance();
+							Some(self.either('=', Token::ShlAssign, Token::Shl))
+						},
+						'>' => Some(self.either('=', Token::GreaterEqual, Token::Greater)),
+						'<' => Some(self.either('=', Token::LessEqual, Token::Less)),
+						'=' if self.peek() == Some('>') => {
+							self.advance();
+							Some(Token::FatArrow)
+						},
+						'=' => Some(self.either('=', Token::Equal, Token::Assign)),
+						'!' => Some(self.either('=', Token::UnEqual, Token::Not)),
+						_ => None,
+					};
+
+					match token {
+						Some(token) => token,
+						None => {
+							self.error(format!("Invalid character: '{}'", ch), self.span_from(start));
+							continue;
+						}
 					}
 				},
-				'|' => {
-                    current_char.next();
-					if current_char.peek() == Some(&'|') {
-						tokens.push(Token::Or);
-						current_char.next();
-					} else {
-						lexer.error();
-					}
-                },
-				'>' => {
-					current_char.next();
-					if current_char.peek() == Some(&'=') {
-						tokens.push(Token::GreaterEqual);
-						current_char.next();
-					} else {
-						tokens.push(Token::Greater);
-					}
+			};
+
+			return Some(Spanned::new(token, self.span_from(start)));
+		}
+
+		None
+
+	}
+
+	fn line_comment(&mut self) {
+		while let Some(ch) = self.peek() {
+			if ch == '\n' {
+				break;
+			}
+			self.advance();
+		}
+	}
+
+	/// Skips a `/* ... */` comment, which may contain further nested block comments.
+	fn block_comment(&mut self) {
+		let start = self.mark();
+		let mut depth = 0;
+
+		loop {
+			match (self.peek(), self.peek_next()) {
+				(Some('/'), Some('*')) => {
+					self.advance();
+					self.advance();
+					depth += 1;
 				},
-				'<' => {
-					current_char.next();
-					if current_char.peek() == Some(&'=') {
-						tokens.push(Token::LessEqual);
-						current_char.next();
-					} else {
-						tokens.push(Token::Less);
+				(Some('*'), Some('/')) => {
+					self.advance();
+					self.advance();
+					depth -= 1;
+					if depth == 0 {
+						break;
 					}
 				},
-				'=' => {
-					current_char.next();
-					if current_char.peek() == Some(&'=') {
-						tokens.push(Token::Equal);
-						current_char.next();
-					} else {
-						tokens.push(Token::Assign);
+				(Some(_), _) => {
+					self.advance();
+				},
+				(None, _) => {
+					self.error("Unterminated block comment", self.span_from(start));
+					break;
+				},
+			}
+		}
+	}
+
+	/// Consumes `next` if it follows, choosing between a two-character and a one-character token.
+	fn either(&mut self, next: char, double: Token, single: Token) -> Token {
+		if self.peek() == Some(next) {
+			self.advance();
+			double
+		} else {
+			single
+		}
+	}
+
+	/// A numeric literal: decimal with optional fraction and exponent (`6.02E23`, `1e-9`),
+	/// or a `0x`/`0b`/`0o` integer. `_` may be used anywhere as a digit separator.
+	/// Literals without a fraction or exponent are integers, widened to big integers when
+	/// they do not fit in 64 bits; an `r` suffix makes a decimal literal an exact rational.
+	fn number(&mut self) -> Token {
+		let start = self.mark();
+		let begin = self.pos;
+
+		let radix = match (self.peek(), self.peek_next()) {
+			(Some('0'), Some('x' | 'X')) => 16,
+			(Some('0'), Some('b' | 'B')) => 2,
+			(Some('0'), Some('o' | 'O')) => 8,
+			_ => 10,
+		};
+
+		let value = if radix == 10 {
+			self.decimal(start)
+		} else {
+			self.advance();
+			self.advance();
+			let mut digits = String::new();
+			self.digits(radix, &mut digits);
+
+			if digits.is_empty() {
+				self.error("Missing digits after the radix prefix", self.span_from(start));
+				None
+			} else {
+				BigInt::parse_bytes(digits.as_bytes(), radix).map(integer)
+			}
+		};
+
+		let trailing = |lexer: &Lexer| match lexer.peek() {
+			Some('.') => lexer.peek_next().is_some_and(|ch| ch.is_ascii_digit()),
+			Some(ch) => ch.is_alphanumeric() || ch == '_',
+			None => false,
+		};
+
+		if trailing(self) {
+			while trailing(self) || self.peek().is_some_and(|ch| ch.is_ascii_digit()) {
+				self.advance();
+			}
+			self.error(format!("Invalid number literal: {}", self.chars[begin..self.pos].iter().collect::<String>()), self.span_from(start));
+			return Token::Int(0);
+		}
+
+		value.unwrap_or(Token::Int(0))
+	}
+
+	fn decimal(&mut self, start: Span) -> Option<Token> {
+		let mut text = String::new();
+		self.digits(10, &mut text);
+
+		if self.peek() == Some('.') && self.peek_next() != Some('.') {
+			text.push('.');
+			self.advance();
+			self.digits(10, &mut text);
+		}
+
+		if matches!(self.peek(), Some('e' | 'E')) {
+			let sign = matches!(self.peek_next(), Some('+' | '-'));
+			let digit = if sign { self.peek_at(2) } else { self.peek_next() };
+
+			if digit.is_some_and(|ch| ch.is_ascii_digit()) {
+				text.push('e');
+				self.advance();
+				if sign {
+					text.extend(self.advance());
+				}
+				self.digits(10, &mut text);
+			}
+		}
+
+		if text == "." {
+			self.error("Invalid number literal: .", self.span_from(start));
+			return None;
+		}
+
+		if self.peek() == Some('r') && !self.peek_next().is_some_and(|ch| ch.is_alphanumeric() || ch == '_') {
+			self.advance();
+			return match rational(&text) {
+				Ok(value) => Some(Token::Rational(value)),
+				Err(message) => {
+					self.error(message, self.span_from(start));
+					None
+				},
+			};
+		}
+
+		if text.contains(['.', 'e']) {
+			text.parse().ok().map(Token::Float)
+		} else {
+			text.parse().ok().map(integer)
+		}
+	}
+
+	/// Consumes digits of the given radix into `text`, skipping `_` separators.
+	fn digits(&mut self, radix: u32, text: &mut String) {
+		while let Some(ch) = self.peek() {
+			if ch.is_digit(radix) {
+				text.push(ch);
+			} else if ch != '_' {
+				break;
+			}
+			self.advance();
+		}
+	}
+
+	/// A double-quoted string. Each `${expr}` inside it is lexed as code, turning the
+	/// literal into a `Token::Interpolation`.
+	fn string(&mut self) -> Token {
+		let start = self.mark();
+		let mut segments = Vec::new();
+		let mut result = String::new();
+
+		self.advance();
+		loop {
+			match self.peek() {
+				Some('"') => {
+					self.advance();
+					break;
+				},
+				Some('\n') | None => {
+					self.error("Unterminated string (use \"\"\" for multi-line strings)", self.span_from(start));
+					break;
+				},
+				Some('\\') => {
+					let escape = self.mark();
+					self.advance();
+					if let Err(message) = unescape(&mut LexerChars(self), &mut result) {
+						self.error(message, self.span_from(escape));
 					}
 				},
-				'!' => {
-					current_char.next();
-					if current_char.peek() == Some(&'=') {
-						tokens.push(Token::UnEqual);
-						current_char.next();
-					} else {
-						tokens.push(Token::Not);
+				Some('$') if self.peek_next() == Some('{') => {
+					if !result.is_empty() {
+						segments.push(Segment::Text(std::mem::take(&mut result)));
 					}
+					self.advance();
+					self.advance();
+					segments.push(Segment::Code(self.interpolation(start)));
+				},
+				Some(ch) => {
+					result.push(ch);
+					self.advance();
 				},
-				_ => lexer.error(),
 			}
 		}
-		tokens.push(Token::EOF);
-		Ok(tokens)
+
+		debug!("String result: {}", result.clone());
+		if segments.is_empty() {
+			Token::String(result)
+		} else {
+			if !result.is_empty() {
+				segments.push(Segment::Text(result));
+			}
+			Token::Interpolation(segments)
+		}
 	}
 
-	fn number(&self, chars: &mut std::iter::Peekable<std::str::Chars>) -> f64 {
-		let mut result = String::new();
+	/// The tokens of an embedded `${...}` expression, up to its matching closing brace.
+	fn interpolation(&mut self, string: Span) -> Vec<Spanned<Token>> {
+		let mut tokens = Vec::new();
+		let mut depth = 0;
 
-		while let Some(&ch) = chars.peek() {
-			if ch.is_digit(10) || ch == '.' {
-				result.push(ch);
-				chars.next();
-			} else {
+		loop {
+			let Some(token) = self.next_token() else {
+				self.error("Unterminated interpolation in string", self.span_from(string));
 				break;
+			};
+
+			match token.node {
+				Token::LBrace => depth += 1,
+				Token::RBrace if depth == 0 => {
+					tokens.push(Spanned::new(Token::Eof, token.span));
+					return tokens;
+				},
+				Token::RBrace => depth -= 1,
+				_ => (),
 			}
+			tokens.push(token);
 		}
 
-		result.parse().unwrap_or_else(|_| self.error())
+		tokens.push(Spanned::new(Token::Eof, self.mark()));
+		tokens
 	}
 
-	fn string(&self, chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+	/// Whether the `r` under the cursor starts a raw string (`r"..."`, `r#"..."#`, ...).
+	fn is_raw_string(&self) -> bool {
+		let mut n = 1;
+		while self.peek_at(n) == Some('#') {
+			n += 1;
+		}
+		self.peek_at(n) == Some('"')
+	}
+
+	/// A raw string: no escapes are processed and the body ends at a quote followed
+	/// by as many `#` as were used to open it.
+	fn raw_string(&mut self) -> String {
+		let start = self.mark();
 		let mut result = String::new();
 
-		chars.next();
-		while let Some(&ch) = chars.peek() {
-			if ch != '"' {
-                match ch {
-                    '\\' => {
-						chars.next();
-						result.push(
-						if let Some(ch) = chars.peek() {
-								match ch {
-									'"' => '"',
-									'\\' => '\\',
-									'/' => '/',
-									'b' => 0x08 as char, // \b
-									'f' => 0x0c as char, // \f
-									'n' => '\n',
-									'r' => '\r',
-									't' => '\t',
-									'u' => {
-										'\0' // todo
-									}
-									'0' => '\0',
-									_ => {
-										return Err(format!("Unknown character escape: '\\{}'", ch));
-									}
-								}
-							} else {
-								return Err(format!("The string has not ended yet!"));
-							}
-						);
-					},
-                    '\n' => return Err(format!("Unexpected string ending: \\n")),
-                    _ => result.push(ch)
-                };
-				chars.next();
+		self.advance();
+		let mut hashes = 0;
+		while self.peek() == Some('#') {
+			self.advance();
+			hashes += 1;
+		}
+		self.advance();
+
+		loop {
+			match self.advance() {
+				Some('"') if (0..hashes).all(|n| self.peek_at(n) == Some('#')) => {
+					for _ in 0..hashes {
+						self.advance();
+					}
+					break;
+				},
+				Some(ch) => result.push(ch),
+				None => {
+					self.error("Unterminated raw string", self.span_from(start));
+					break;
+				},
+			}
+		}
+
+		result
+	}
+
+	/// A `"""` string spanning several lines. A line break right after the opening quotes
+	/// and a blank last line before the closing ones are dropped, and the indentation common
+	/// to all non-blank lines is removed before escapes are processed.
+	fn multiline_string(&mut self) -> String {
+		let start = self.mark();
+		let mut text = String::new();
+
+		for _ in 0..3 {
+			self.advance();
+		}
+
+		loop {
+			match self.peek() {
+				Some('"') if self.peek_next() == Some('"') && self.peek_at(2) == Some('"') => {
+					for _ in 0..3 {
+						self.advance();
+					}
+					break;
+				},
+				Some('\\') => {
+					text.push('\\');
+					self.advance();
+					if let Some(ch) = self.advance() {
+						text.push(ch);
+					}
+				},
+				Some(ch) => {
+					text.push(ch);
+					self.advance();
+				},
+				None => {
+					self.error("Unterminated multi-line string", self.span_from(start));
+					break;
+				},
+			}
+		}
+
+		let text = dedent(&text);
+		let mut result = String::new();
+		let mut chars = text.chars();
+		while let Some(ch) = chars.next() {
+			if ch == '\\' {
+				if let Err(message) = unescape(&mut chars, &mut result) {
+					self.error(message, self.span_from(start));
+				}
 			} else {
-				break;
+				result.push(ch);
 			}
 		}
 
-		chars.next();
-		debug!("String result: {}", result.clone());	
-		Ok(result)
+		result
 	}
 
-	fn identifier(&self, chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+	fn identifier(&mut self) -> String {
 		let mut result = String::new();
 
-		while let Some(&ch) = chars.peek() {
+		while let Some(ch) = self.peek() {
 			if ch.is_alphanumeric() || ch == '_' {
 				result.push(ch);
-				chars.next();
+				self.advance();
 			} else {
 				break;
 			}
@@ -213,3 +580,138 @@ impl Lexer {
 		result
 	}
 }
+
+fn integer(value: BigInt) -> Token {
+	match value.to_i64() {
+		Some(value) => Token::Int(value),
+		None => Token::BigInt(value),
+	}
+}
+
+/// The exact value of a decimal literal such as `0.1` or `1.5e-3`.
+fn rational(text: &str) -> Result<BigRational, String> {
+	let too_large = || format!("Rational literal is too large: {}r", text);
+	let (mantissa, exponent) = match text.split_once('e') {
+		Some((mantissa, exponent)) => (mantissa, exponent.parse::<i64>().map_err(|_| too_large())?),
+		None => (text, 0),
+	};
+	let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+	let digits: BigInt = format!("0{}{}", whole, fraction).parse().unwrap_or_default();
+	let exponent = exponent.checked_sub(fraction.len() as i64).ok_or_else(too_large)?;
+	// 10 ** n takes a little under 10 / 3 * n bits.
+	if exponent.unsigned_abs().saturating_mul(10) / 3 > MAX_BITS {
+		return Err(too_large());
+	}
+	let scale = BigInt::from(10).pow(exponent.unsigned_abs());
+
+	Ok(if exponent >= 0 {
+		BigRational::from_integer(digits * scale)
+	} else {
+		BigRational::new(digits, scale)
+	})
+}
+
+/// Lets the escape decoder pull characters straight from the lexer.
+struct LexerChars<'a>(&'a mut Lexer);
+
+impl Iterator for LexerChars<'_> {
+	type Item = char;
+
+	fn next(&mut self) -> Option<char> {
+		self.0.advance()
+	}
+}
+
+/// Decodes the escape sequence following a backslash and appends it to `result`.
+fn unescape(chars: &mut impl Iterator<Item = char>, result: &mut String) -> Result<(), String> {
+	let Some(ch) = chars.next() else {
+		return Err("The string has not ended yet!".to_string());
+	};
+
+	result.push(match ch {
+		'"' => '"',
+		'\\' => '\\',
+		'$' => '$',
+		'/' => '/',
+		'b' => 0x08 as char, // \b
+		'f' => 0x0c as char, // \f
+		'n' => '\n',
+		'r' => '\r',
+		't' => '\t',
+		'0' => '\0',
+		'u' => unicode_escape(chars)?,
+		_ => return Err(format!("Unknown character escape: '\\{}'", ch)),
+	});
+
+	Ok(())
+}
+
+/// `\u{1F600}` with one to six hex digits, or `\uXXXX` where a high surrogate
+/// must be followed by a `\uXXXX` low surrogate.
+fn unicode_escape(chars: &mut impl Iterator<Item = char>) -> Result<char, String> {
+	let code = match chars.next() {
+		Some('{') => {
+			let mut digits = String::new();
+			loop {
+				match chars.next() {
+					Some('}') => break,
+					Some(ch) if ch.is_ascii_hexdigit() && digits.len() < 6 => digits.push(ch),
+					_ => return Err("Invalid unicode escape: expected 1 to 6 hex digits in '\\u{...}'".to_string()),
+				}
+			}
+			if digits.is_empty() {
+				return Err("Empty unicode escape: '\\u{}'".to_string());
+			}
+			let code = u32::from_str_radix(&digits, 16).unwrap_or(u32::MAX);
+			return char::from_u32(code).ok_or_else(|| format!("Invalid unicode scalar value: {:X}", code));
+		},
+		first => hex4(first.into_iter().chain(chars.by_ref().take(3)))?,
+	};
+
+	match code {
+		0xD800..=0xDBFF => {
+			if chars.next() != Some('\\') || chars.next() != Some('u') {
+				return Err(format!("Unpaired surrogate: \\u{:04X}", code));
+			}
+			let low = hex4(chars.take(4))?;
+			if !(0xDC00..=0xDFFF).contains(&low) {
+				return Err(format!("Invalid low surrogate: \\u{:04X}", low));
+			}
+			let code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+			char::from_u32(code).ok_or_else(|| format!("Invalid unicode scalar value: {:X}", code))
+		},
+		0xDC00..=0xDFFF => Err(format!("Unpaired surrogate: \\u{:04X}", code)),
+		_ => char::from_u32(code).ok_or_else(|| format!("Invalid unicode scalar value: {:X}", code)),
+	}
+}
+
+fn hex4(chars: impl Iterator<Item = char>) -> Result<u32, String> {
+	let digits: String = chars.collect();
+	if digits.len() != 4 || !digits.chars().all(|ch| ch.is_ascii_hexdigit()) {
+		return Err("Invalid unicode escape: expected 4 hex digits in '\\uXXXX'".to_string());
+	}
+	Ok(u32::from_str_radix(&digits, 16).unwrap_or(0))
+}
+
+fn dedent(text: &str) -> String {
+	let text = text.strip_prefix("\r\n").or_else(|| text.strip_prefix('\n')).unwrap_or(text);
+	let mut lines: Vec<&str> = text.split('\n').collect();
+
+	if lines.len() > 1 && lines.last().is_some_and(|line| line.trim().is_empty()) {
+		lines.pop();
+	}
+
+	let indent = lines
+		.iter()
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| line.len() - line.trim_start().len())
+		.min()
+		.unwrap_or(0);
+
+	lines
+		.iter()
+		.map(|line| line.get(indent..).unwrap_or_else(|| line.trim_start()))
+		.collect::<Vec<&str>>()
+		.join("\n")
+}
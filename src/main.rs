@@ -1,15 +1,16 @@
-use ast_node::ASTNode;
 use log::{error, debug};
 use std::io::{self, Write};
 use env_logger::Env;
-use std::error::Error;
+use std::process::ExitCode;
 use std::path::Path;
 use std::ffi::OsStr;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::fs;
 use std::env;
 use chrono::Utc;
 
+mod span;
+mod error;
 mod token;
 mod value;
 mod control_flow;
@@ -18,11 +19,21 @@ mod lexer;
 mod parser;
 mod interpreter;
 mod environment;
+mod iteration;
+mod pattern;
+mod resolver;
+mod optimizer;
+mod bytecode;
+mod compiler;
+mod vm;
 mod builtin;
 mod test;
 
-use token::Token;
-use interpreter::Interpreter;
+use interpreter::{Interpreter, MAX_DEPTH};
+use vm::Vm;
+use value::Value;
+use bytecode::Program;
+use error::{LimError, ErrorKind};
 
 /// An Interpreter for Lim
 #[derive(Parser, Debug)]
@@ -30,6 +41,27 @@ use interpreter::Interpreter;
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// How source code is run
+    #[arg(long, global = true, value_enum, default_value_t = Backend::Tree)]
+    backend: Backend,
+
+    /// Optimization level: 0 for none, 1 to fold constants and drop dead code, 2 to also
+    /// inline small functions
+    #[arg(short = 'O', global = true, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=2))]
+    optimize: u8,
+
+    /// How many calls may be nested before a recursion error
+    #[arg(long, global = true, default_value_t = MAX_DEPTH, value_parser = max_depth)]
+    max_depth: usize,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Backend {
+    /// Walk the syntax tree
+    Tree,
+    /// Compile to bytecode and run it on the stack VM
+    Vm,
 }
 
 #[derive(Subcommand, Debug)]
@@ -46,11 +78,78 @@ enum Commands {
         input: String,
         #[arg(required = false)]
         output: Option<String>,
+        /// Emit bytecode (.limc) instead of the syntax tree (.lim)
+        #[arg(long)]
+        bytecode: bool,
     },
 }
 
-fn input_loop(interpreter: &mut Interpreter) -> Result<(), Box<dyn Error>> {
-    print!("Lim {} (Time: {}) on {}({})\n", 
+/// What the REPL and `Run` need from either backend.
+trait Engine {
+    fn init(&mut self) -> Result<(), LimError>;
+    fn set_redeclare(&mut self, redeclare: bool);
+    fn set_optimization(&mut self, level: u8);
+    fn set_max_depth(&mut self, max_depth: usize);
+    fn interpret(&mut self, text: String) -> Result<Value, LimError>;
+}
+
+impl Engine for Interpreter {
+    fn init(&mut self) -> Result<(), LimError> {
+        Interpreter::init(self)
+    }
+
+    fn set_redeclare(&mut self, redeclare: bool) {
+        Interpreter::set_redeclare(self, redeclare)
+    }
+
+    fn set_optimization(&mut self, level: u8) {
+        Interpreter::set_optimization(self, level)
+    }
+
+    fn set_max_depth(&mut self, max_depth: usize) {
+        Interpreter::set_max_depth(self, max_depth)
+    }
+
+    fn interpret(&mut self, text: String) -> Result<Value, LimError> {
+        Interpreter::interpret(self, text)
+    }
+}
+
+impl Engine for Vm {
+    fn init(&mut self) -> Result<(), LimError> {
+        Vm::init(self)
+    }
+
+    fn set_redeclare(&mut self, redeclare: bool) {
+        Vm::set_redeclare(self, redeclare)
+    }
+
+    fn set_optimization(&mut self, level: u8) {
+        Vm::set_optimization(self, level)
+    }
+
+    fn set_max_depth(&mut self, max_depth: usize) {
+        Vm::set_max_depth(self, max_depth)
+    }
+
+    fn interpret(&mut self, text: String) -> Result<Value, LimError> {
+        Vm::interpret(self, text)
+    }
+}
+
+fn engine(cli: &Cli) -> Result<Box<dyn Engine>, LimError> {
+    let mut engine: Box<dyn Engine> = match cli.backend {
+        Backend::Tree => Box::new(Interpreter::new()),
+        Backend::Vm => Box::new(Vm::new()),
+    };
+    engine.init()?;
+    engine.set_optimization(cli.optimize);
+    engine.set_max_depth(cli.max_depth);
+    Ok(engine)
+}
+
+fn input_loop(interpreter: &mut dyn Engine) -> Result<ExitCode, LimError> {
+    println!("Lim {} (Time: {}) on {}({})", 
         env!("CARGO_PKG_VERSION"), 
         Utc::now().to_rfc2822(), 
         env::consts::OS, 
@@ -69,55 +168,92 @@ fn input_loop(interpreter: &mut Interpreter) -> Result<(), Box<dyn Error>> {
             continue;
         }
 
-        match interpreter.interpret(text) {
+        match interpreter.interpret(text.clone()) {
             Ok(result) => println!("{}", result),
-            Err(e) => error!("Error: {}", e),
+            Err(e) => error!("{}", e.report(&text)),
         }
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("error")).init();
+fn cannot(action: &str, path: &str, cause: LimError) -> LimError {
+    LimError::new(ErrorKind::Io, format!("Cannot {} {}", action, path)).caused_by(cause)
+}
 
-    let mut interpreter = Interpreter::new();
-    interpreter.init().expect("Init Error: ");
+fn invalid(path: &str, cause: bincode::Error) -> LimError {
+    LimError::new(ErrorKind::Format, format!("{} is not a valid built program", path)).caused_by(cause.into())
+}
 
-    let cli = Cli::parse();
-    if let Some(command) = cli.command {
+fn run(mut cli: Cli) -> Result<ExitCode, LimError> {
+    if let Some(command) = cli.command.take() {
         match command {
             Commands::Loop => {
-                input_loop(&mut interpreter)
+                let mut interpreter = engine(&cli)?;
+                interpreter.set_redeclare(true);
+                input_loop(interpreter.as_mut())
             },
 
             Commands::Run { input } => {
                 let path = Path::new(&input);
 
-                if path.extension() == Some(OsStr::new("lim")) {
-                    let bytes = fs::read(input)?;
+                if path.extension() == Some(OsStr::new("lim")) || path.extension() == Some(OsStr::new("limc")) {
+                    let bytes = fs::read(&input).map_err(|e| cannot("read", &input, e.into()))?;
 
-                    match interpreter.evaluate(&bincode::deserialize(&bytes[..]).unwrap()) {
-                        Ok(result) => println!("{}", result.unwrap()),
-                        Err(e) => error!("Error: {}", e),
+                    // A built file decides the backend: an AST is walked, bytecode runs on the VM.
+                    let result = if path.extension() == Some(OsStr::new("lim")) {
+                        let mut interpreter = Interpreter::new();
+                        interpreter.init()?;
+                        interpreter.set_max_depth(cli.max_depth);
+                        interpreter.execute(bincode::deserialize(&bytes[..]).map_err(|e| invalid(&input, e))?)
+                    } else {
+                        let mut vm = Vm::new();
+                        vm.init()?;
+                        vm.set_max_depth(cli.max_depth);
+                        vm.run(bincode::deserialize::<Program>(&bytes[..]).map_err(|e| invalid(&input, e))?)
+                    };
+
+                    match result {
+                        Ok(result) => println!("{}", result),
+                        Err(e) => {
+                            error!("{}", e);
+                            return Ok(ExitCode::FAILURE);
+                        },
                     }
                 
                 } else {
-                    let text = std::fs::read_to_string(input)?;
+                    let text = std::fs::read_to_string(&input).map_err(|e| cannot("read", &input, e.into()))?;
+                    let mut interpreter = engine(&cli)?;
 
-                    match interpreter.interpret(text) {
+                    match interpreter.interpret(text.clone()) {
                         Ok(result) => println!("{}", result),
-                        Err(e) => error!("Error: {}", e),
+                        Err(e) => {
+                            error!("{}", e.report(&text));
+                            return Ok(ExitCode::FAILURE);
+                        },
                     }
                 }
 
-                Ok(())
+                Ok(ExitCode::SUCCESS)
             },
 
-            Commands::Build { input, output } => {
-                let text = std::fs::read_to_string(&input)?;
-                let mut parser = crate::parser::Parser::new(text)?;
+            Commands::Build { input, output, bytecode } => {
+                let text = std::fs::read_to_string(&input).map_err(|e| cannot("read", &input, e.into()))?;
 
-                match parser.parse() {
-                    Ok(result) => {
+                // Programs are resolved against the builtins, which a fresh backend has declared.
+                let built = if bytecode {
+                    let mut vm = Vm::new();
+                    vm.init()?;
+                    vm.set_optimization(cli.optimize);
+                    vm.compile(text.clone()).and_then(|program| Ok(bincode::serialize(&program)?))
+                } else {
+                    let mut interpreter = Interpreter::new();
+                    interpreter.init()?;
+                    interpreter.set_optimization(cli.optimize);
+                    interpreter.prepare(text.clone()).and_then(|ast| Ok(bincode::serialize(&ast)?))
+                };
+                let extension = if bytecode { "limc" } else { "lim" };
+
+                match built {
+                    Ok(bytes) => {
                         let path = match output {
                             Some(path) => {
                                 path.clone()
@@ -132,29 +268,65 @@ fn main() -> Result<(), Box<dyn Error>> {
                                 
                                 path
                                     .join("..")
-                                    .join(format!("{}.lim", name))
+                                    .join(format!("{}.{}", name, extension))
                                     .to_str()
                                     .expect("Cannot convert output path to str.")
                                     .to_string()
                                 
                             }
                         };
-                        let output_file = fs::File::create(path)?;
+                        let output_file = fs::File::create(&path).map_err(|e| cannot("create", &path, e.into()))?;
                         let mut writer = std::io::BufWriter::new(output_file);
-                        let bytes = bincode::serialize(&result)?;
                         writer.write_all(&bytes[..])?;
                     },
                     Err(e) => {
-                        error!("Error: {}", e);
-                        return Err(e.into());
+                        error!("{}", e.report(&text));
+                        return Ok(ExitCode::FAILURE);
                     }
                 }
 
-                Ok(())
+                Ok(ExitCode::SUCCESS)
             },
         }
     } else {
-        input_loop(&mut interpreter)
+        let mut interpreter = engine(&cli)?;
+        interpreter.set_redeclare(true);
+        input_loop(interpreter.as_mut())
+    }
+}
+
+/// The largest stack the interpreter thread is given, which bounds `--max-depth`.
+const MAX_STACK: usize = 4 << 30;
+
+fn max_depth(text: &str) -> Result<usize, String> {
+    let depth: usize = text.parse().map_err(|e| format!("{}", e))?;
+    let limit = (MAX_STACK - interpreter::BASE_STACK) / interpreter::CALL_STACK;
+    match depth <= limit {
+        true => Ok(depth),
+        false => Err(format!("at most {} nested calls are supported", limit)),
     }
 }
 
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(Env::default().default_filter_or("error")).init();
+
+    let cli = Cli::parse();
+
+    // Run on a thread whose stack fits `--max-depth` calls, so that deep recursion ends in
+    // a recursion error rather than overflowing the native stack.
+    let depth = cli.max_depth;
+    let stack = interpreter::stack_size(depth);
+    let result = std::thread::Builder::new()
+        .stack_size(stack)
+        .spawn(move || run(cli))
+        .map_err(|e| LimError::new(ErrorKind::Resource, format!("Cannot reserve {} MiB of stack for --max-depth {}", stack >> 20, depth)).caused_by(e.into()))
+        .and_then(|thread| thread.join().unwrap_or_else(|_| Err(LimError::runtime("The interpreter panicked"))));
+
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            error!("{}", e.report(""));
+            ExitCode::FAILURE
+        },
+    }
+}
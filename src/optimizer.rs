@@ -0,0 +1,561 @@
+use std::collections::{HashMap, HashSet};
+use std::iter;
+
+use crate::ast_node::{ASTNode, ASTKind, MatchArm, Slot};
+use crate::span::Span;
+use crate::token::Token;
+use crate::value::Value;
+
+/// Functions whose bodies have at most this many nodes are inlined.
+const INLINE_LIMIT: usize = 24;
+
+/// A function whose calls can be replaced by its body.
+#[derive(Clone, Debug)]
+struct Inline {
+    params: Vec<String>,
+    body: ASTNode,
+}
+
+/// Rewrites a resolved program into a cheaper one that behaves the same. Level 1 folds
+/// operations on literals and drops code that can never run; level 2 also inlines calls to
+/// small functions that use nothing but their parameters.
+///
+/// An operation on literals that would fail is left in place, to fail at runtime with its
+/// own span. Every scope the program opens is kept, so the slots the resolver recorded stay
+/// valid, and the result resolves to the same slots again when a built program is loaded.
+#[derive(Debug)]
+pub struct Optimizer {
+    pub level: u8,
+    /// Whether functions bound to globals may be inlined. Off in the REPL, where a later
+    /// line may assign to the global.
+    pub globals: bool,
+    /// Names assigned anywhere in the program. Functions bound to them are not inlined.
+    assigned: HashSet<String>,
+    /// The inlinable functions of each open scope, by slot index. The first is the global
+    /// scope.
+    scopes: Vec<HashMap<usize, Inline>>,
+    inlined: usize,
+}
+
+impl Optimizer {
+    pub fn new() -> Self {
+        Self {
+            level: 0,
+            globals: true,
+            assigned: HashSet::new(),
+            scopes: vec![],
+            inlined: 0,
+        }
+    }
+
+    pub fn optimize(&mut self, program: ASTNode) -> ASTNode {
+        if self.level == 0 {
+            return program;
+        }
+
+        self.assigned.clear();
+        assignments(&program, &mut self.assigned);
+        self.scopes = vec![HashMap::new()];
+
+        // Top-level statements run in the global scope, which is already open.
+        let program = match program.kind {
+            ASTKind::Block { statements } => ASTNode::new(ASTKind::Block { statements: self.statements(statements) }, program.span),
+            _ => self.node(program),
+        };
+
+        self.scopes.clear();
+        program
+    }
+
+    fn statements(&mut self, statements: Vec<ASTNode>) -> Vec<ASTNode> {
+        // Declared functions are defined before the block runs, so their bodies are done
+        // first, seeing only the functions defined by then.
+        let mut statements: Vec<ASTNode> = statements.into_iter().map(|statement| match statement.kind {
+            ASTKind::FunctionDeclaration { name, params, body, slot } => {
+                let body = self.function(*body);
+                if let Some(slot) = slot {
+                    self.register(&name, slot, &params, &body);
+                }
+                ASTNode::new(ASTKind::FunctionDeclaration { name, params, body: Box::new(body), slot }, statement.span)
+            },
+            _ => statement,
+        }).collect();
+
+        // Nothing after a `return`, `break` or `continue` runs. Declarations stay, reduced to the name,
+        // so that every variable keeps its slot.
+        if let Some(end) = statements.iter().position(|statement| matches!(statement.kind, ASTKind::Return(_) | ASTKind::Break { .. } | ASTKind::Continue { .. })) {
+            let unreachable = statements.split_off(end + 1);
+            statements.extend(unreachable.into_iter().filter_map(|statement| match statement.kind {
+                ASTKind::FunctionDeclaration { .. } => Some(statement),
+                ASTKind::Let { ast } => {
+                    let ast = match ast.kind {
+                        ASTKind::Assignment { name, slot, .. } => ASTNode::new(ASTKind::Identifier { name, slot }, ast.span),
+                        _ => *ast,
+                    };
+                    Some(ASTNode::new(ASTKind::Let { ast: Box::new(ast) }, statement.span))
+                },
+                _ => None,
+            }));
+        }
+
+        statements.into_iter().map(|statement| match statement.kind {
+            ASTKind::FunctionDeclaration { .. } => statement,
+            _ => self.node(statement),
+        }).collect()
+    }
+
+    fn scoped(&mut self, node: ASTNode) -> ASTNode {
+        self.scopes.push(HashMap::new());
+
+        let node = match node.kind {
+            ASTKind::Block { statements } => ASTNode::new(ASTKind::Block { statements: self.statements(statements) }, node.span),
+            _ => self.node(node),
+        };
+
+        self.scopes.pop();
+        node
+    }
+
+    /// Optimizes a function body inside the scope holding the parameters and `self`.
+    fn function(&mut self, body: ASTNode) -> ASTNode {
+        self.scopes.push(HashMap::new());
+        let body = self.node(body);
+        self.scopes.pop();
+        body
+    }
+
+    /// Records a function bound to `slot` of the innermost scope, if its calls can be
+    /// inlined: it is small, uses no variable but its parameters (so it is not recursive),
+    /// cannot `return`, `break` or `continue` out of the caller, and nothing assigns to its name.
+    fn register(&mut self, name: &str, slot: Slot, params: &[String], body: &ASTNode) {
+        if self.level < 2 || self.assigned.contains(name) || (self.scopes.len() == 1 && !self.globals) {
+            return;
+        }
+
+        // A declaration directly as the body would be hoisted above the parameters.
+        if matches!(body.kind, ASTKind::Let { .. } | ASTKind::FunctionDeclaration { .. }) || size(body) > INLINE_LIMIT || escapes(body, false) {
+            return;
+        }
+
+        let mut body = body.clone();
+        let mut closed = true;
+        references(&mut body, 0, &mut |_, slot, inner| {
+            closed &= slot.depth < inner || (slot.depth == inner && slot.index < params.len());
+        });
+
+        if closed {
+            let scope = self.scopes.last_mut().expect("no scope is open");
+            scope.insert(slot.index, Inline { params: params.to_vec(), body });
+        }
+    }
+
+    fn candidate(&self, function: &ASTNode) -> Option<Inline> {
+        let ASTKind::Identifier { slot: Some(slot), .. } = &function.kind else {
+            return None;
+        };
+
+        let scope = self.scopes.len().checked_sub(slot.depth + 1)?;
+        self.scopes[scope].get(&slot.index).cloned()
+    }
+
+    /// Replaces a call with a block binding the arguments to the parameters, followed by
+    /// the body. The block takes the place of the scope a call opens, so the body's slots
+    /// are unchanged; the parameters are renamed so that loading the result resolves the
+    /// arguments to the same variables as before.
+    fn inline(&mut self, function: Inline, arguments: Vec<ASTNode>, span: Span) -> ASTNode {
+        self.inlined += 1;
+        let names: Vec<String> = function.params.iter().map(|param| format!("{}#{}", param, self.inlined)).collect();
+
+        let mut body = function.body;
+        references(&mut body, 0, &mut |name, slot, inner| {
+            if slot.depth == inner {
+                *name = names[slot.index].clone();
+            }
+        });
+
+        let mut statements: Vec<ASTNode> = names.iter().zip(arguments).enumerate().map(|(index, (name, mut argument))| {
+            // The arguments are now evaluated one scope further in.
+            references(&mut argument, 0, &mut |_, slot, inner| {
+                if slot.depth >= inner {
+                    slot.depth += 1;
+                }
+            });
+
+            let span = argument.span;
+            let ast = ASTNode::new(ASTKind::Assignment { name: name.clone(), value: Box::new(argument), slot: Some(Slot { depth: 0, index }) }, span);
+            ASTNode::new(ASTKind::Let { ast: Box::new(ast) }, span)
+        }).collect();
+        statements.push(body);
+
+        ASTNode::new(ASTKind::Block { statements }, span)
+    }
+
+    fn node(&mut self, node: ASTNode) -> ASTNode {
+        let span = node.span;
+        let literal = |value| ASTNode::new(ASTKind::Literal(value), span);
+
+        let kind = match node.kind {
+            ASTKind::Block { .. } => return self.scoped(node),
+
+            ASTKind::FunctionDefinition { params, body } => {
+                ASTKind::FunctionDefinition { params, body: Box::new(self.function(*body)) }
+            },
+
+            ASTKind::FunctionDeclaration { name, params, body, slot } => {
+                ASTKind::FunctionDeclaration { name, params, body: Box::new(self.function(*body)), slot }
+            },
+
+            ASTKind::FunctionCall { function, arguments } => {
+                let function = self.node(*function);
+                let arguments: Vec<ASTNode> = arguments.into_iter().map(|argument| self.node(argument)).collect();
+
+                match self.candidate(&function) {
+                    Some(inline) if inline.params.len() == arguments.len() => return self.inline(inline, arguments, span),
+                    _ => ASTKind::FunctionCall { function: Box::new(function), arguments },
+                }
+            },
+
+            ASTKind::BinaryOperation { operator, left, right } => {
+                let left = self.node(*left);
+                let right = self.node(*right);
+
+                if let (ASTKind::Literal(l), ASTKind::Literal(r)) = (&left.kind, &right.kind) {
+                    if let Ok(value) = l.arithmetic(&operator, r) {
+                        return literal(value);
+                    }
+                }
+
+                ASTKind::BinaryOperation { operator, left: Box::new(left), right: Box::new(right) }
+            },
+
+            ASTKind::LogicalOperation { operator, left, right } => {
+                let left = self.node(*left);
+                let right = self.node(*right);
+
+                match (&operator, &left.kind, &right.kind) {
+                    // The left operand decides, so the right one never runs.
+                    (Token::And | Token::Or, ASTKind::Literal(Value::Boolean(l)), _) if *l == (operator == Token::Or) => {
+                        return literal(Value::Boolean(*l));
+                    },
+                    (Token::And | Token::Or, ASTKind::Literal(Value::Boolean(_)), ASTKind::Literal(Value::Boolean(r))) => {
+                        return literal(Value::Boolean(*r));
+                    },
+                    (Token::And | Token::Or, _, _) => (),
+                    (_, ASTKind::Literal(l), ASTKind::Literal(r)) => {
+                        if let Ok(result) = l.relation(&operator, r) {
+                            return literal(Value::Boolean(result));
+                        }
+                    },
+                    _ => (),
+                }
+
+                ASTKind::LogicalOperation { operator, left: Box::new(left), right: Box::new(right) }
+            },
+
+            ASTKind::UnaryOperation { operator, operand } => {
+                let operand = self.node(*operand);
+
+                if let ASTKind::Literal(value) = &operand.kind {
+                    if let Ok(value) = value.unary(&operator) {
+                        return literal(value);
+                    }
+                }
+
+                ASTKind::UnaryOperation { operator, operand: Box::new(operand) }
+            },
+
+            ASTKind::Let { ast } => {
+                let ast = match ast.kind {
+                    ASTKind::Assignment { name, value, slot } => {
+                        let value = self.node(*value);
+                        if let (ASTKind::FunctionDefinition { params, body }, Some(slot)) = (&value.kind, slot) {
+                            self.register(&name, slot, params, body);
+                        }
+                        ASTNode::new(ASTKind::Assignment { name, value: Box::new(value), slot }, ast.span)
+                    },
+                    ASTKind::Destructure { pattern, value } => {
+                        ASTNode::new(ASTKind::Destructure { pattern, value: Box::new(self.node(*value)) }, ast.span)
+                    },
+                    _ => *ast,
+                };
+
+                ASTKind::Let { ast: Box::new(ast) }
+            },
+
+            ASTKind::IndexAssignment { name, indices, operator, value, slot } => {
+                let indices = indices.into_iter().map(|index| self.node(index)).collect();
+                ASTKind::IndexAssignment { name, indices, operator, value: Box::new(self.node(*value)), slot }
+            },
+
+            ASTKind::Destructure { pattern, value } => {
+                ASTKind::Destructure { pattern, value: Box::new(self.node(*value)) }
+            },
+
+            ASTKind::Assignment { name, value, slot } => {
+                ASTKind::Assignment { name, value: Box::new(self.node(*value)), slot }
+            },
+
+            ASTKind::Conditional { condition, true_branch, false_branch } => {
+                let condition = self.node(*condition);
+
+                // Only the branch taken is kept, as a block standing for the branch's scope.
+                if let ASTKind::Literal(value) = &condition.kind {
+                    let branch = if matches!(value, Value::Boolean(true)) { Some(*true_branch) } else { false_branch.map(|branch| *branch) };
+
+                    return match branch {
+                        Some(branch @ ASTNode { kind: ASTKind::Block { .. }, .. }) => self.node(branch),
+                        Some(branch) => self.node(ASTNode::new(ASTKind::Block { statements: vec![branch] }, span)),
+                        None => literal(Value::Null),
+                    };
+                }
+
+                ASTKind::Conditional {
+                    condition: Box::new(condition),
+                    true_branch: Box::new(self.scoped(*true_branch)),
+                    false_branch: false_branch.map(|branch| Box::new(self.scoped(*branch))),
+                }
+            },
+
+            ASTKind::Loop { condition, body, label } => {
+                let condition = self.node(*condition);
+
+                if let ASTKind::Literal(value) = &condition.kind {
+                    if !matches!(value, Value::Boolean(true)) {
+                        return literal(Value::Null);
+                    }
+                }
+
+                ASTKind::Loop { condition: Box::new(condition), body: Box::new(self.scoped(*body)), label }
+            },
+
+            ASTKind::For { variable, iterable, body, slot, label } => {
+                let iterable = self.node(*iterable);
+                self.scopes.push(HashMap::new());
+                let body = self.scoped(*body);
+                self.scopes.pop();
+
+                ASTKind::For { variable, iterable: Box::new(iterable), body: Box::new(body), slot, label }
+            },
+
+            ASTKind::Match { scrutinee, arms } => {
+                let scrutinee = self.node(*scrutinee);
+                let arms = arms.into_iter().map(|arm| {
+                    self.scopes.push(HashMap::new());
+                    let guard = arm.guard.map(|guard| self.node(guard));
+                    let body = self.scoped(arm.body);
+                    self.scopes.pop();
+                    MatchArm { pattern: arm.pattern, guard, body }
+                }).collect();
+
+                ASTKind::Match { scrutinee: Box::new(scrutinee), arms }
+            },
+
+            ASTKind::Interpolation(parts) => ASTKind::Interpolation(parts.into_iter().map(|part| self.node(part)).collect()),
+            ASTKind::Tuple(items) => ASTKind::Tuple(items.into_iter().map(|item| self.node(item)).collect()),
+            ASTKind::Vector(items) => ASTKind::Vector(items.into_iter().map(|item| self.node(item)).collect()),
+
+            ASTKind::Index { expression, index } => {
+                ASTKind::Index { expression: Box::new(self.node(*expression)), index: Box::new(self.node(*index)) }
+            },
+
+            ASTKind::Return(value) => ASTKind::Return(Box::new(self.node(*value))),
+
+            ASTKind::Break { label, value } => ASTKind::Break { label, value: value.map(|value| Box::new(self.node(*value))) },
+
+            kind @ (ASTKind::Literal(_) | ASTKind::Identifier { .. } | ASTKind::Continue { .. }) => kind,
+        };
+
+        ASTNode::new(kind, span)
+    }
+}
+
+fn children(node: &ASTNode) -> Vec<&ASTNode> {
+    match &node.kind {
+        ASTKind::Block { statements } => statements.iter().collect(),
+        ASTKind::FunctionDefinition { body, .. } | ASTKind::FunctionDeclaration { body, .. } => vec![body],
+        ASTKind::FunctionCall { function, arguments } => iter::once(function.as_ref()).chain(arguments).collect(),
+        ASTKind::BinaryOperation { left, right, .. } | ASTKind::LogicalOperation { left, right, .. } => vec![left, right],
+        ASTKind::UnaryOperation { operand, .. } => vec![operand],
+        ASTKind::Assignment { value, .. } | ASTKind::Destructure { value, .. } => vec![value],
+        ASTKind::IndexAssignment { indices, value, .. } => indices.iter().chain(iter::once(value.as_ref())).collect(),
+        ASTKind::Let { ast } => vec![ast],
+        ASTKind::Conditional { condition, true_branch, false_branch } => {
+            [condition, true_branch].into_iter().chain(false_branch).map(|node| node.as_ref()).collect()
+        },
+        ASTKind::Loop { condition, body, .. } => vec![condition, body],
+        ASTKind::For { iterable, body, .. } => vec![iterable, body],
+        ASTKind::Match { scrutinee, arms } => {
+            iter::once(scrutinee.as_ref()).chain(arms.iter().flat_map(|arm| arm.guard.iter().chain([&arm.body]))).collect()
+        },
+        ASTKind::Interpolation(nodes) | ASTKind::Tuple(nodes) | ASTKind::Vector(nodes) => nodes.iter().collect(),
+        ASTKind::Index { expression, index } => vec![expression, index],
+        ASTKind::Return(value) => vec![value],
+        ASTKind::Break { value, .. } => value.iter().map(|value| value.as_ref()).collect(),
+        ASTKind::Literal(_) | ASTKind::Identifier { .. } | ASTKind::Continue { .. } => vec![],
+    }
+}
+
+fn size(node: &ASTNode) -> usize {
+    1 + children(node).into_iter().map(size).sum::<usize>()
+}
+
+fn assignments(node: &ASTNode, names: &mut HashSet<String>) {
+    match &node.kind {
+        ASTKind::Assignment { name, value, .. } => {
+            names.insert(name.clone());
+            assignments(value, names);
+        },
+        ASTKind::IndexAssignment { name, indices, value, .. } => {
+            names.insert(name.clone());
+            indices.iter().chain(iter::once(value.as_ref())).for_each(|child| assignments(child, names));
+        },
+        ASTKind::Destructure { pattern, value } => {
+            names.extend(pattern.names().into_iter().map(str::to_string));
+            assignments(value, names);
+        },
+        // The assignment in a `let` declares the names rather than assigning them.
+        ASTKind::Let { ast } => {
+            if let ASTKind::Assignment { value, .. } | ASTKind::Destructure { value, .. } = &ast.kind {
+                assignments(value, names);
+            }
+        },
+        _ => children(node).into_iter().for_each(|child| assignments(child, names)),
+    }
+}
+
+/// Whether running `node` as a function body could `return`, or `break` or `continue` out
+/// of the body.
+fn escapes(node: &ASTNode, in_loop: bool) -> bool {
+    match &node.kind {
+        ASTKind::Return(_) => true,
+        ASTKind::Break { .. } | ASTKind::Continue { .. } if !in_loop => true,
+        ASTKind::FunctionDefinition { .. } | ASTKind::FunctionDeclaration { .. } => false,
+        ASTKind::Loop { condition, body, .. } => escapes(condition, in_loop) || escapes(body, true),
+        ASTKind::For { iterable, body, .. } => escapes(iterable, in_loop) || escapes(body, true),
+        _ => children(node).into_iter().any(|child| escapes(child, in_loop)),
+    }
+}
+
+/// Calls `f` on the name and slot of every variable read or assigned in `node`, with the
+/// number of scopes the use is nested in within `node`. Scopes open where the resolver
+/// opens them.
+fn references(node: &mut ASTNode, inner: usize, f: &mut impl FnMut(&mut String, &mut Slot, usize)) {
+    match &mut node.kind {
+        ASTKind::Block { .. } => scoped_references(node, inner, f),
+
+        ASTKind::FunctionDefinition { body, .. } | ASTKind::FunctionDeclaration { body, .. } => references(body, inner + 1, f),
+
+        ASTKind::Identifier { name, slot } => {
+            if let Some(slot) = slot {
+                f(name, slot, inner);
+            }
+        },
+
+        ASTKind::Assignment { name, value, slot } => {
+            references(value, inner, f);
+            if let Some(slot) = slot {
+                f(name, slot, inner);
+            }
+        },
+
+        ASTKind::IndexAssignment { name, indices, value, slot, .. } => {
+            for index in indices {
+                references(index, inner, f);
+            }
+            references(value, inner, f);
+            if let Some(slot) = slot {
+                f(name, slot, inner);
+            }
+        },
+
+        ASTKind::Destructure { pattern, value } => {
+            references(value, inner, f);
+            for (name, slot) in pattern.bindings() {
+                if let Some(slot) = slot {
+                    f(name, slot, inner);
+                }
+            }
+        },
+
+        ASTKind::Let { ast } => {
+            if let ASTKind::Assignment { value, .. } | ASTKind::Destructure { value, .. } = &mut ast.kind {
+                references(value, inner, f);
+            }
+        },
+
+        ASTKind::Conditional { condition, true_branch, false_branch } => {
+            references(condition, inner, f);
+            scoped_references(true_branch, inner, f);
+            if let Some(false_branch) = false_branch {
+                scoped_references(false_branch, inner, f);
+            }
+        },
+
+        ASTKind::Loop { condition, body, .. } => {
+            references(condition, inner, f);
+            scoped_references(body, inner, f);
+        },
+
+        // The body's scope is inside the one holding the loop variable.
+        ASTKind::For { iterable, body, .. } => {
+            references(iterable, inner, f);
+            scoped_references(body, inner + 1, f);
+        },
+
+        // Like the body of a `for`, an arm's guard and body are inside the bindings' scope.
+        ASTKind::Match { scrutinee, arms } => {
+            references(scrutinee, inner, f);
+            for arm in arms {
+                if let Some(guard) = &mut arm.guard {
+                    references(guard, inner + 1, f);
+                }
+                scoped_references(&mut arm.body, inner + 1, f);
+            }
+        },
+
+        ASTKind::FunctionCall { function, arguments } => {
+            references(function, inner, f);
+            for argument in arguments {
+                references(argument, inner, f);
+            }
+        },
+
+        ASTKind::BinaryOperation { left, right, .. } | ASTKind::LogicalOperation { left, right, .. } => {
+            references(left, inner, f);
+            references(right, inner, f);
+        },
+
+        ASTKind::Index { expression: left, index: right } => {
+            references(left, inner, f);
+            references(right, inner, f);
+        },
+
+        ASTKind::UnaryOperation { operand, .. } => references(operand, inner, f),
+        ASTKind::Return(value) => references(value, inner, f),
+
+        ASTKind::Break { value, .. } => {
+            if let Some(value) = value {
+                references(value, inner, f);
+            }
+        },
+
+        ASTKind::Interpolation(nodes) | ASTKind::Tuple(nodes) | ASTKind::Vector(nodes) => {
+            for node in nodes {
+                references(node, inner, f);
+            }
+        },
+
+        ASTKind::Literal(_) | ASTKind::Continue { .. } => (),
+    }
+}
+
+fn scoped_references(node: &mut ASTNode, inner: usize, f: &mut impl FnMut(&mut String, &mut Slot, usize)) {
+    match &mut node.kind {
+        ASTKind::Block { statements } => {
+            for statement in statements {
+                references(statement, inner + 1, f);
+            }
+        },
+        _ => references(node, inner + 1, f),
+    }
+}
@@ -1,153 +1,182 @@
 use crate::lexer::Lexer;
-use crate::token::Token;
-use crate::ast_node::ASTNode;
+use crate::token::{Token, Segment};
+use crate::ast_node::{ASTNode, ASTKind, MatchArm};
+use crate::pattern::Pattern;
+use crate::span::{Span, Spanned};
+use crate::value::Value;
+use crate::error::LimError;
 use crate::debug;
 
 use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<Spanned<Token>>,
 	pos: usize,
 }
 
 impl Parser {
-    pub fn new(text: String) -> Result<Self, String> {
-        Ok(Self { 
-            tokens: Lexer::tokenize(text)?, 
+    pub fn new(text: String) -> Result<Self, LimError> {
+        Ok(Self {
+            tokens: Lexer::tokenize(text)?,
             pos: 0,
         })
     }
 
-    fn cur_token(&mut self) -> Option<&Token> {
-        if self.pos < self.tokens.len() {
-            Some(&self.tokens[self.pos])
-        } else {
-            None
-        }
+    fn cur_token(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|token| &token.node)
     }
 
-    fn cur_token_clone(&mut self) -> Option<Token> {
-        if let Some(token) = self.cur_token() {
-            Some(token.clone())
-        } else {
-            None
-        }
+    fn cur_token_clone(&self) -> Option<Token> {
+        self.cur_token().cloned()
     }
 
-    fn cur_token_equals(&mut self, token: Token) -> bool {
+    fn cur_token_equals(&self, token: Token) -> bool {
         self.cur_token() == Some(&token)
     }
 
-    fn cur_token_in(&mut self, tokens: &[Token]) -> bool {
+    fn cur_token_is_not(&self, tokens: &[Token]) -> bool {
         let token = self.cur_token();
         if let Some(token) = token {
-            tokens.contains(token)
+            !tokens.contains(token)
         } else {
-            false
+            true
         }
     }
 
-    fn cur_token_is_not(&mut self, tokens: &[Token]) -> bool {
-        let token = self.cur_token();
-        if let Some(token) = token {
-            !tokens.contains(token)
-        } else {
-            true
+    fn peek_token(&self) -> Option<&Token> {
+        self.tokens.get(self.pos + 1).map(|token| &token.node)
+    }
+
+    fn cur_token_unwrap(&self) -> Token {
+        self.cur_token_clone().unwrap_or(Token::Eof)
+    }
+
+    /// Span of the current token, or of the end of input once it has been consumed.
+    fn cur_span(&self) -> Span {
+        match self.tokens.get(self.pos) {
+            Some(token) => token.span,
+            None => self.tokens.last().map(|token| token.span).unwrap_or_default(),
         }
     }
 
-    fn cur_token_unwrap(&mut self) -> Token {
-        self.cur_token_clone().expect("Current Token Unwrap(None)")
+    /// Span of the most recently consumed token.
+    fn prev_span(&self) -> Span {
+        match self.pos.checked_sub(1).and_then(|pos| self.tokens.get(pos)) {
+            Some(token) => token.span,
+            None => self.cur_span(),
+        }
+    }
+
+    /// Builds a node spanning from `start` to the end of the last consumed token.
+    fn node(&self, kind: ASTKind, start: Span) -> ASTNode {
+        ASTNode::new(kind, start.to(self.prev_span()))
     }
-    
+
     fn next(&mut self) {
         self.pos += 1;
     }
 
-    fn next_token(&mut self) -> Option<Token> {
-        self.next();
-        self.cur_token_clone()
-    }
-    
-    fn eat(&mut self, expected_token: Token) -> Result<(), String> {
+    fn eat(&mut self, expected_token: Token) -> Result<(), LimError> {
         if expected_token != self.cur_token_unwrap() {
-            return Err(format!("Expected {}, found {:?}", expected_token, self.cur_token()));
-        } 
+            return Err(LimError::parse(format!("Expected {}, found {}", expected_token, self.cur_token_unwrap()), self.cur_span()));
+        }
 
         self.next();
         Ok(())
     }
 
-    pub fn parse(&mut self) -> Result<ASTNode, String> {
+    pub fn parse(&mut self) -> Result<ASTNode, LimError> {
         debug!("{:?}", self.tokens);
         self.statements()
     }
 
-    fn statements(&mut self) -> Result<ASTNode, String> {
+    fn statements(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
         let mut statements = vec![];
-        
-        while self.cur_token_is_not(&[Token::EOF, Token::RBrace]) {
+
+        while self.cur_token_is_not(&[Token::Eof, Token::RBrace]) {
+            // Statements ending in a block (`if`, loops, `{ ... }`, `fn name() { ... }`) need
+            // no semicolon after them.
+            let block_like = matches!(self.cur_token(), Some(Token::If | Token::Match | Token::While | Token::For | Token::Loop | Token::Label(_) | Token::LBrace));
             let stmt = self.statement()?;
+            let block_like = block_like || matches!(stmt.kind, ASTKind::FunctionDeclaration { .. });
             statements.push(stmt);
-            
+
             if self.cur_token_equals(Token::Semicolon) {
                 self.next();
-            } else if self.cur_token_is_not(&[Token::EOF, Token::RBrace]) {
-                return Err(format!("Expected semicolon, found: {}!", self.cur_token_unwrap()));
+            } else if !block_like && self.cur_token_is_not(&[Token::Eof, Token::RBrace]) {
+                return Err(LimError::parse(format!("Expected semicolon, found: {}!", self.cur_token_unwrap()), self.cur_span()));
             }
         }
-        
-        Ok(ASTNode::Block { statements: Box::new(statements) })
+
+        Ok(self.node(ASTKind::Block { statements }, start))
     }
 
-    fn statement(&mut self) -> Result<ASTNode, String> {
+    fn statement(&mut self) -> Result<ASTNode, LimError> {
         debug!("{:?}", self.cur_token_clone());
+        let start = self.cur_span();
 
         if let Some(token) = self.cur_token() {
             match *token {
-                Token::If => {
-                    self.next();
-                    let condition = Box::new(self.expression()?);
-                    let true_branch = Box::new(self.statement()?);
-                    let mut false_branch = None;
-                    if self.cur_token_equals(Token::Else) {
-                        self.next();
-                        false_branch = Some(Box::new(self.statement()?));
-                    }
-                    Ok(ASTNode::Conditional { condition, true_branch, false_branch })
-                },
-    
-                Token::While => {
-                    self.next();
-                    let condition = Box::new(self.expression()?);
-                    let body = Box::new(self.statement()?);
-                    Ok(ASTNode::Loop { condition, body })
-                },
-    
+                // At the start of a statement these end the statement, so that
+                // `if c { ... }\n(x)` is not read as a call on the `if`.
+                Token::If => self.conditional(),
+                Token::Match => self.match_expression(),
+                Token::While | Token::For | Token::Loop | Token::Label(_) => self.loop_expression(),
+                Token::LBrace => self.block(),
+
                 Token::Break => {
                     self.next();
-                    Ok(ASTNode::Break)
+                    let label = self.label();
+                    // `break` takes a value unless the statement or match arm ends with it.
+                    let value = match self.cur_token_is_not(&[Token::Semicolon, Token::Comma, Token::RBrace, Token::Eof]) {
+                        true => Some(Box::new(self.expression()?)),
+                        false => None,
+                    };
+                    Ok(self.node(ASTKind::Break { label, value }, start))
+                }
+
+                Token::Continue => {
+                    self.next();
+                    let label = self.label();
+                    Ok(self.node(ASTKind::Continue { label }, start))
                 }
-    
+
                 Token::Return => {
                     self.next();
-                    Ok(ASTNode::Return(self.statement()?.into()))
+                    let value = self.statement()?;
+                    Ok(self.node(ASTKind::Return(value.into()), start))
                 },
-    
+
                 Token::Let => {
                     self.next();
-                    Ok(ASTNode::Let { ast: Box::new(self.expression()?) })
-                    
+                    let ast = match self.cur_token() {
+                        Some(Token::Identifier(name)) if name != "_" => {
+                            // `let v[0] = 1`, `let x += 1` or `let x + 1` is an expression, but declares nothing.
+                            if self.peek_token().and_then(Token::compound_operator).is_some() {
+                                return Err(LimError::parse("A let declaration cannot use a compound assignment!", self.cur_span()));
+                            }
+                            let ast = self.expression()?;
+                            if !matches!(ast.kind, ASTKind::Identifier { .. } | ASTKind::Assignment { .. }) {
+                                return Err(LimError::parse("Expected a variable name or a pattern after let!", ast.span));
+                            }
+                            ast
+                        },
+                        _ => {
+                            let pattern = self.pattern()?;
+                            self.eat(Token::Assign)?;
+                            let value = Box::new(self.expression()?);
+                            self.node(ASTKind::Destructure { pattern, value }, start)
+                        },
+                    };
+                    Ok(self.node(ASTKind::Let { ast: Box::new(ast) }, start))
                 },
-    
-                Token::LBrace => {
-                    self.next();
-                    let block = self.statements();
-                    self.eat(Token::RBrace)?;
-                    block
+
+                Token::FN if matches!(self.peek_token(), Some(Token::Identifier(_))) => {
+                    self.function_declaration()
                 },
-    
+
                 _ => {
                     self.expression()
                 }
@@ -155,34 +184,148 @@ impl Parser {
         } else {
             self.expression()
         }
-        
+
+    }
+
+    fn conditional(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        self.eat(Token::If)?;
+
+        let condition = Box::new(self.expression()?);
+        let true_branch = Box::new(self.statement()?);
+        let mut false_branch = None;
+        if self.cur_token_equals(Token::Else) {
+            self.next();
+            false_branch = Some(Box::new(self.statement()?));
+        }
+
+        Ok(self.node(ASTKind::Conditional { condition, true_branch, false_branch }, start))
+    }
+
+    /// `match value { pattern => body, pattern if guard => body }`. The comma after an
+    /// arm may be left out when its body is a block.
+    fn match_expression(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        self.eat(Token::Match)?;
+
+        let scrutinee = Box::new(self.expression()?);
+        self.eat(Token::LBrace)?;
+
+        let mut arms = vec![];
+        while !self.cur_token_equals(Token::RBrace) {
+            let pattern = self.pattern()?;
+            let guard = match self.cur_token_equals(Token::If) {
+                true => {
+                    self.next();
+                    Some(self.expression()?)
+                },
+                false => None,
+            };
+            self.eat(Token::FatArrow)?;
+
+            let block = self.cur_token_equals(Token::LBrace);
+            let body = self.statement()?;
+            arms.push(MatchArm { pattern, guard, body });
+
+            if self.cur_token_equals(Token::Comma) {
+                self.next();
+            } else if !block && !self.cur_token_equals(Token::RBrace) {
+                return Err(LimError::parse(format!("Expected comma after match arm, found: {}!", self.cur_token_unwrap()), self.cur_span()));
+            }
+        }
+
+        self.eat(Token::RBrace)?;
+        Ok(self.node(ASTKind::Match { scrutinee, arms }, start))
+    }
+
+    /// A `while`, `for` or `loop` loop, which may be labeled: `'outer: for x in v { ... }`.
+    fn loop_expression(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        let label = self.label();
+        if label.is_some() {
+            self.eat(Token::Colon)?;
+        }
+
+        match self.cur_token_unwrap() {
+            Token::While => self.while_loop(start, label),
+            Token::For => self.for_loop(start, label),
+            Token::Loop => {
+                self.next();
+                let condition = Box::new(ASTNode::new(ASTKind::Literal(Value::Boolean(true)), start));
+                let body = Box::new(self.statement()?);
+                Ok(self.node(ASTKind::Loop { condition, body, label }, start))
+            },
+            token => Err(LimError::parse(format!("Expected a loop after the label, found: {}!", token), self.cur_span())),
+        }
+    }
+
+    /// The label of a loop, or the one after `break` or `continue`, if there is one.
+    fn label(&mut self) -> Option<String> {
+        let Some(Token::Label(label)) = self.cur_token_clone() else {
+            return None;
+        };
+        self.next();
+        Some(label)
+    }
+
+    fn while_loop(&mut self, start: Span, label: Option<String>) -> Result<ASTNode, LimError> {
+        self.eat(Token::While)?;
+
+        let condition = Box::new(self.expression()?);
+        let body = Box::new(self.statement()?);
+        Ok(self.node(ASTKind::Loop { condition, body, label }, start))
+    }
+
+    fn for_loop(&mut self, start: Span, label: Option<String>) -> Result<ASTNode, LimError> {
+        self.eat(Token::For)?;
+
+        let variable = match self.cur_token_unwrap() {
+            Token::Identifier(name) => name,
+            token => return Err(LimError::parse(format!("Expected loop variable, found: {}!", token), self.cur_span())),
+        };
+        self.next();
+        self.eat(Token::In)?;
+
+        let iterable = Box::new(self.expression()?);
+        let body = Box::new(self.statement()?);
+        Ok(self.node(ASTKind::For { variable, iterable, body, slot: None, label }, start))
+    }
+
+    fn block(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        self.eat(Token::LBrace)?;
+
+        let block = self.statements()?;
+        self.eat(Token::RBrace)?;
+        Ok(self.node(block.kind, start))
     }
 
-    fn expression(&mut self) -> Result<ASTNode, String> {
+    fn expression(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+
         match self.cur_token() {
             Some(Token::FN) => self.function_definition(),
             _ => {
+                if matches!(self.cur_token(), Some(Token::LParen | Token::LBracket)) {
+                    if let Some(node) = self.destructuring()? {
+                        return Ok(node);
+                    }
+                }
+
                 let mut node = self.assignment()?;
 
-                if self.cur_token_equals(Token::Assign) {
+                let operator = self.cur_token().and_then(Token::compound_operator);
+                if self.cur_token_equals(Token::Assign) || operator.is_some() {
                     self.next();
                     let value = Box::new(self.expression()?);
-
-                    match node {
-                        ASTNode::Identifier(name) => {
-                            node = ASTNode::Assignment { name, value };
-                        },
-                        _ => {
-                            return Err(format!("Invalid assignment to: {:?}!", node.clone()));
-                        }
-                    }
+                    node = self.assignment_to(node, operator, value, start)?;
                 } else if self.cur_token_equals(Token::Question) {
                     self.eat(Token::Question)?;
                     let left = self.expression()?;
                     self.eat(Token::Colon)?;
                     let right = self.expression()?;
 
-                    node = ASTNode::Conditional { condition: Box::new(node), true_branch: Box::new(left), false_branch: Some(Box::new(right)) };
+                    node = self.node(ASTKind::Conditional { condition: Box::new(node), true_branch: Box::new(left), false_branch: Some(Box::new(right)) }, start);
                 }
 
                 Ok(node)
@@ -190,212 +333,559 @@ impl Parser {
         }
     }
 
-    fn assignment(&mut self) -> Result<ASTNode, String> {
-        self.logical_or()
+    /// An assignment to a variable or to an item of a vector in one, `v[i][j] = value`.
+    /// `x += value` is `x = x + value`; for an item the operator is kept, so that the
+    /// indices are evaluated once.
+    fn assignment_to(&mut self, target: ASTNode, operator: Option<Token>, value: Box<ASTNode>, start: Span) -> Result<ASTNode, LimError> {
+        let span = target.span;
+        let mut indices = vec![];
+        let mut root = target;
+
+        while let ASTKind::Index { expression, index } = root.kind {
+            indices.push(*index);
+            root = *expression;
+        }
+        indices.reverse();
+
+        let ASTKind::Identifier { name, .. } = root.kind else {
+            return Err(LimError::parse(format!("Invalid assignment to: {:?}!", root.kind), span));
+        };
+
+        if !indices.is_empty() {
+            return Ok(self.node(ASTKind::IndexAssignment { name, indices, operator, value, slot: None }, start));
+        }
+
+        let value = match operator {
+            Some(operator) => {
+                let variable = Box::new(ASTNode::new(ASTKind::Identifier { name: name.clone(), slot: None }, span));
+                Box::new(self.node(ASTKind::BinaryOperation { operator, left: variable, right: value }, start))
+            },
+            None => value,
+        };
+        Ok(self.node(ASTKind::Assignment { name, value, slot: None }, start))
+    }
+
+    /// `(a, b) = value` or `[first, ..rest] = value`. A tuple or vector is only known to
+    /// be a pattern once the `=` after it is reached, so anything else is parsed again as
+    /// an expression.
+    fn destructuring(&mut self) -> Result<Option<ASTNode>, LimError> {
+        let start = self.cur_span();
+        let pos = self.pos;
+
+        match self.pattern() {
+            Ok(pattern) if self.cur_token_equals(Token::Assign) => {
+                self.next();
+                let value = Box::new(self.expression()?);
+                Ok(Some(self.node(ASTKind::Destructure { pattern, value }, start)))
+            },
+            _ => {
+                self.pos = pos;
+                Ok(None)
+            },
+        }
+    }
+
+    fn assignment(&mut self) -> Result<ASTNode, LimError> {
+        self.range()
+    }
+
+    /// `start..end` or `start..=end`, binding looser than every other binary operator.
+    fn range(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        let node = self.logical_or()?;
+
+        match self.cur_token_clone() {
+            Some(operator @ (Token::DotDot | Token::DotDotEq)) => {
+                self.next();
+                let end = self.logical_or()?;
+                Ok(self.node(ASTKind::BinaryOperation { operator, left: node.into(), right: end.into() }, start))
+            },
+            _ => Ok(node),
+        }
     }
 
-    fn logical_or(&mut self) -> Result<ASTNode, String> {
+    fn logical_or(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
         let mut node = self.logical_and()?;
 
         while self.cur_token_equals(Token::Or) {
             self.next();
             let right = self.logical_and()?;
-            node = ASTNode::LogicalOperation { operator: Token::Or, left: Box::new(node), right: Box::new(right) };
+            node = self.node(ASTKind::LogicalOperation { operator: Token::Or, left: Box::new(node), right: Box::new(right) }, start);
         }
 
         Ok(node)
     }
 
-    fn logical_and(&mut self) -> Result<ASTNode, String> {
+    fn logical_and(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
         let mut node = self.equality()?;
 
         while self.cur_token_equals(Token::And) {
             self.next();
             let right = self.equality()?;
-            node = ASTNode::LogicalOperation { operator: Token::And, left: Box::new(node), right: Box::new(right) };
+            node = self.node(ASTKind::LogicalOperation { operator: Token::And, left: Box::new(node), right: Box::new(right) }, start);
         }
 
         Ok(node)
     }
 
-    fn equality(&mut self) -> Result<ASTNode, String> {
+    fn equality(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
         let mut node = self.relational()?;
 
         while let Some(token @ (Token::Equal | Token::UnEqual)) = self.cur_token_clone() {
             self.next();
             let right = self.relational()?;
-            node = ASTNode::LogicalOperation { operator: token, left: Box::new(node), right: Box::new(right) };
+            node = self.node(ASTKind::LogicalOperation { operator: token, left: Box::new(node), right: Box::new(right) }, start);
         }
 
         Ok(node)
     }
 
-    fn relational(&mut self) -> Result<ASTNode, String> {
-        let mut node = self.additive_expression()?;
+    fn relational(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        let mut node = self.bitwise_or()?;
 
         while let Some(token @ (Token::Greater | Token::Less | Token::GreaterEqual | Token::LessEqual)) = self.cur_token_clone() {
+            self.next();
+            let right = self.bitwise_or()?;
+            node = self.node(ASTKind::LogicalOperation { operator: token, left: Box::new(node), right: Box::new(right) }, start);
+        }
+
+        Ok(node)
+    }
+
+    fn bitwise_or(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        let mut node = self.bitwise_xor()?;
+
+        while self.cur_token_equals(Token::BitOr) {
+            self.next();
+            let right = self.bitwise_xor()?;
+            node = self.node(ASTKind::BinaryOperation { operator: Token::BitOr, left: node.into(), right: right.into() }, start);
+        }
+
+        Ok(node)
+    }
+
+    fn bitwise_xor(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        let mut node = self.bitwise_and()?;
+
+        while self.cur_token_equals(Token::BitXor) {
+            self.next();
+            let right = self.bitwise_and()?;
+            node = self.node(ASTKind::BinaryOperation { operator: Token::BitXor, left: node.into(), right: right.into() }, start);
+        }
+
+        Ok(node)
+    }
+
+    fn bitwise_and(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        let mut node = self.shift_expression()?;
+
+        while self.cur_token_equals(Token::BitAnd) {
+            self.next();
+            let right = self.shift_expression()?;
+            node = self.node(ASTKind::BinaryOperation { operator: Token::BitAnd, left: node.into(), right: right.into() }, start);
+        }
+
+        Ok(node)
+    }
+
+    fn shift_expression(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        let mut node = self.additive_expression()?;
+
+        while let Some(token @ (Token::Shl | Token::Shr)) = self.cur_token_clone() {
             self.next();
             let right = self.additive_expression()?;
-            node = ASTNode::LogicalOperation { operator: token, left: Box::new(node), right: Box::new(right) };
+            node = self.node(ASTKind::BinaryOperation { operator: token, left: node.into(), right: right.into() }, start);
         }
 
         Ok(node)
     }
 
-    fn additive_expression(&mut self) -> Result<ASTNode, String> {
+    fn additive_expression(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
         let mut node = self.multiplicative_expression()?;
 
         while let Some(token @ (Token::Plus | Token::Minus)) = self.cur_token_clone() {
             self.next();
             let right = self.multiplicative_expression()?;
-            node = ASTNode::BinaryOperation { operator: token, left: node.into(), right: right.into() };
+            node = self.node(ASTKind::BinaryOperation { operator: token, left: node.into(), right: right.into() }, start);
         }
 
         Ok(node)
     }
 
-    fn multiplicative_expression(&mut self) -> Result<ASTNode, String> {
+    fn multiplicative_expression(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
         let mut node = self.unary_expression()?;
 
         while let Some(token @ (Token::Mul | Token::Div | Token::Mod)) = self.cur_token_clone() {
             self.next();
             let right = self.unary_expression()?;
-            node = ASTNode::BinaryOperation { operator: token, left: node.into(), right: right.into() };
+            node = self.node(ASTKind::BinaryOperation { operator: token, left: node.into(), right: right.into() }, start);
         }
 
         Ok(node)
     }
 
-    fn unary_expression(&mut self) -> Result<ASTNode, String> {
-        if let Some(token @ (Token::Plus | Token::Minus | Token::Not)) = self.cur_token_clone() {
+    fn unary_expression(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+
+        if let Some(token @ (Token::Plus | Token::Minus | Token::Not | Token::BitNot)) = self.cur_token_clone() {
             self.next();
-            let expr = self.index_expression()?;
-            Ok(ASTNode::UnaryOperation { operator: token, operand: Box::new(expr) })
+            let expr = self.unary_expression()?;
+            Ok(self.node(ASTKind::UnaryOperation { operator: token, operand: Box::new(expr) }, start))
         } else {
-            self.index_expression()
+            self.power_expression()
         }
     }
 
-    fn index_expression(&mut self) -> Result<ASTNode, String> {
-        let mut result = self.primary()?;
+    /// `**` binds tighter than a unary operator on its left and is right-associative,
+    /// so `-2 ** 2` is `-(2 ** 2)` and `2 ** 3 ** 2` is `2 ** (3 ** 2)`.
+    fn power_expression(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        let base = self.index_expression()?;
 
-        while self.cur_token_equals(Token::LBracket) {
+        if self.cur_token_equals(Token::Pow) {
             self.next();
-            result = ASTNode::Index { expression: Box::new(result), index: Box::new(self.expression()?) };
-            self.eat(Token::RBracket)?;
+            let exponent = self.unary_expression()?;
+            Ok(self.node(ASTKind::BinaryOperation { operator: Token::Pow, left: base.into(), right: exponent.into() }, start))
+        } else {
+            Ok(base)
+        }
+    }
+
+    /// Postfix indexing and calls, which chain left to right: `handlers[0](evt)`, `make_adder(5)(3)`.
+    fn index_expression(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        let mut result = self.primary()?;
+
+        loop {
+            match self.cur_token() {
+                Some(Token::LBracket) => {
+                    self.next();
+                    let index = Box::new(self.expression()?);
+                    self.eat(Token::RBracket)?;
+                    result = self.node(ASTKind::Index { expression: Box::new(result), index }, start);
+                },
+                Some(Token::LParen) => {
+                    result = self.function_call(result, start)?;
+                },
+                _ => break,
+            }
         }
 
         Ok(result)
     }
 
-    fn primary(&mut self) -> Result<ASTNode, String> {
+    fn primary(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
         let token = self.cur_token_unwrap();
 
         match token.clone() {
             Token::Identifier(name) => {
                 self.next();
+                Ok(self.node(ASTKind::Identifier { name, slot: None }, start))
+            },
 
-                if self.cur_token_equals(Token::LParen) {
-                    self.function_call(*name)
-                } else {
-                    Ok(ASTNode::Identifier(name))
-                }
+            Token::Int(_) | Token::BigInt(_) | Token::Rational(_) | Token::Float(_) | Token::String(_) | Token::True | Token::False | Token::Null => {
+                self.next();
+                Ok(self.node(ASTKind::Literal(token.to_value().map_err(|e| e.at(start))?), start))
             },
 
-            Token::Float(_) | Token::String(_) | Token::True | Token::False | Token::Null => {
+            Token::Interpolation(segments) => {
                 self.next();
-                Ok(ASTNode::Literal(token.to_value()?))
+                let mut parts = vec![];
+
+                for segment in segments {
+                    match segment {
+                        Segment::Text(text) => parts.push(ASTNode::new(ASTKind::Literal(Value::String(text)), start)),
+                        Segment::Code(tokens) => {
+                            let mut parser = Parser { tokens, pos: 0 };
+                            let expression = parser.expression()?;
+                            if !parser.cur_token_equals(Token::Eof) {
+                                return Err(LimError::parse(format!("Unexpected token in interpolation: {}!", parser.cur_token_unwrap()), parser.cur_span()));
+                            }
+                            parts.push(expression);
+                        },
+                    }
+                }
+
+                Ok(self.node(ASTKind::Interpolation(parts), start))
             },
 
             Token::LParen => {
-                let tuple = self.tuple()?;
-                if tuple.len() == 1 {
-                    Ok(tuple[0].clone())
+                self.eat(Token::LParen)?;
+                let (mut tuple, trailing_comma) = self.collect_list(Token::RParen)?;
+                self.eat(Token::RParen)?;
+
+                // `(x)` is just a parenthesized expression; `(x,)` is a one-element tuple.
+                if tuple.len() == 1 && !trailing_comma {
+                    Ok(tuple.remove(0))
                 } else {
-                    Ok(ASTNode::Tuple(tuple))
+                    Ok(self.node(ASTKind::Tuple(tuple), start))
                 }
             },
 
             Token::LBracket => {
-                Ok(ASTNode::Vector(self.vector()?))
+                let vector = self.vector()?;
+                Ok(self.node(ASTKind::Vector(vector), start))
             },
 
-            _ => Err(format!("[Parser] Unexpected token: {}!", self.cur_token_unwrap())),
+            Token::If => self.conditional(),
+            Token::Match => self.match_expression(),
+            Token::While | Token::For | Token::Loop | Token::Label(_) => self.loop_expression(),
+            Token::LBrace => self.block(),
+
+            _ => Err(LimError::parse(format!("Unexpected token: {}!", token), start)),
         }
     }
 
 
-    fn function_call(&mut self, name: String) -> Result<ASTNode, String> {
-        Ok(
-            ASTNode::FunctionCall {
-                function: Some(name),
-                arguments: self.tuple()?,
-            }
-        )
+    fn function_call(&mut self, function: ASTNode, start: Span) -> Result<ASTNode, LimError> {
+        let arguments = self.tuple()?;
+
+        Ok(self.node(
+            ASTKind::FunctionCall {
+                function: Box::new(function),
+                arguments,
+            },
+            start,
+        ))
+    }
+
+    fn function_declaration(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
+        self.eat(Token::FN)?;
+
+        let name = match self.cur_token_unwrap() {
+            Token::Identifier(name) => name,
+            token => return Err(LimError::parse(format!("Expected function name, found: {}!", token), self.cur_span())),
+        };
+        self.next();
+
+        let (params, destructured) = self.parameters()?;
+        let body = self.function_body(destructured)?;
+
+        Ok(self.node(
+            ASTKind::FunctionDeclaration {
+                name,
+                params,
+                body: Box::new(body),
+                slot: None,
+            },
+            start,
+        ))
     }
 
-    fn function_definition(&mut self) -> Result<ASTNode, String> {
+    fn function_definition(&mut self) -> Result<ASTNode, LimError> {
+        let start = self.cur_span();
         self.eat(Token::FN)?;
 
-        let params = self.identifier_list()?;
+        let (params, destructured) = self.parameters()?;
         debug!("Params: {:?}", params);
 
-        let body = self.statement()?;
+        let body = self.function_body(destructured)?;
 
-        Ok(ASTNode::FunctionDefinition {
+        Ok(self.node(
+            ASTKind::FunctionDefinition {
                 params,
                 body: Box::new(body),
             },
-        )
-    }
-
-    fn identifier_list(&mut self) -> Result<Vec<String>, String> {
-        let params = self.tuple()?;
-        let result: Vec<String> = 
-            params
-                .iter()
-                .map(|ast| {
-                    match ast {
-                        ASTNode::Identifier(name) => *name.clone(),
-                        _ => panic!("Expected variable name, found: {:?}!", ast),
-                    }
-                })
-                .collect()
-                ;
-        
-        Ok(result)
+            start,
+        ))
+    }
+
+    /// The parameter list of a function, where each parameter is a pattern. One that is
+    /// not a plain name is passed under a name no variable can have, `#0` for the first
+    /// parameter, and returned as a `let` destructuring it.
+    fn parameters(&mut self) -> Result<(Vec<String>, Vec<ASTNode>), LimError> {
+        let start = self.cur_span();
+        self.eat(Token::LParen)?;
+        let (patterns, _) = self.pattern_list(Token::RParen)?;
+        self.eat(Token::RParen)?;
+
+        let mut params = vec![];
+        let mut destructured = vec![];
+        for (index, pattern) in patterns.into_iter().enumerate() {
+            match pattern {
+                Pattern::Binding { name, .. } => params.push(name),
+                Pattern::Rest { .. } => return Err(LimError::parse("A rest pattern can only be used in a tuple or vector pattern!", start)),
+                Pattern::Literal(_) | Pattern::Range { .. } => return Err(LimError::parse("Expected a parameter name, `_` or a tuple or vector pattern!", start)),
+                Pattern::Wildcard => params.push(format!("#{}", index)),
+                pattern => {
+                    let name = format!("#{}", index);
+                    let value = Box::new(ASTNode::new(ASTKind::Identifier { name: name.clone(), slot: None }, start));
+                    let ast = Box::new(ASTNode::new(ASTKind::Destructure { pattern, value }, start));
+                    destructured.push(ASTNode::new(ASTKind::Let { ast }, start));
+                    params.push(name);
+                },
+            }
+        }
+
+        Ok((params, destructured))
+    }
+
+    /// The body of a function, inside a block that first destructures its parameters if
+    /// any need it.
+    fn function_body(&mut self, mut destructured: Vec<ASTNode>) -> Result<ASTNode, LimError> {
+        let body = self.statement()?;
+        if destructured.is_empty() {
+            return Ok(body);
+        }
+
+        let span = body.span;
+        destructured.push(body);
+        Ok(ASTNode::new(ASTKind::Block { statements: destructured }, span))
     }
 
-    fn tuple(&mut self) -> Result<Box<Vec<ASTNode>>, String> {
+    fn tuple(&mut self) -> Result<Vec<ASTNode>, LimError> {
         self.eat(Token::LParen)?;
-        let result = self.collect_list();
+        let (result, _) = self.collect_list(Token::RParen)?;
         self.eat(Token::RParen)?;
-        result
+        Ok(result)
     }
 
-    fn vector(&mut self) -> Result<Box<Vec<ASTNode>>, String> {
+    fn vector(&mut self) -> Result<Vec<ASTNode>, LimError> {
         self.eat(Token::LBracket)?;
-        let result = self.collect_list();
+        let (result, _) = self.collect_list(Token::RBracket)?;
         self.eat(Token::RBracket)?;
-        result
+        Ok(result)
     }
 
-    fn set(&mut self) -> Result<Box<Vec<ASTNode>>, String> {
-        self.eat(Token::LBrace)?;
-        let result = self.collect_list();
-        self.eat(Token::RBrace)?;
-        result
+    /// Parses comma-separated expressions up to (but not including) `end`. The list may be
+    /// empty and may end with a comma; the flag reports whether it did.
+    fn collect_list(&mut self, end: Token) -> Result<(Vec<ASTNode>, bool), LimError> {
+        let mut list = vec![];
+        let mut trailing_comma = false;
+
+        while !self.cur_token_equals(end.clone()) {
+            list.push(self.expression()?);
+            trailing_comma = self.cur_token_equals(Token::Comma);
+
+            if trailing_comma {
+                self.next();
+            } else {
+                break;
+            }
+        }
+
+        Ok((list, trailing_comma))
     }
 
-    fn collect_list(&mut self) -> Result<Box<Vec<ASTNode>>, String> {
-        let mut list = vec![];
+    fn pattern(&mut self) -> Result<Pattern, LimError> {
+        let start = self.cur_span();
+
+        match self.cur_token_unwrap() {
+            Token::Identifier(name) => {
+                self.next();
+                match name.as_str() {
+                    "_" => Ok(Pattern::Wildcard),
+                    _ => Ok(Pattern::Binding { name, slot: None }),
+                }
+            },
+
+            Token::LParen => {
+                self.next();
+                let (mut patterns, trailing_comma) = self.pattern_list(Token::RParen)?;
+                self.eat(Token::RParen)?;
+
+                // As in expressions, `(p)` is only parenthesized; `(p,)` is a tuple.
+                match patterns.len() == 1 && !trailing_comma && !matches!(patterns[0], Pattern::Rest { .. }) {
+                    true => Ok(patterns.remove(0)),
+                    false => Ok(Pattern::Tuple(patterns)),
+                }
+            },
+
+            Token::LBracket => {
+                self.next();
+                let (patterns, _) = self.pattern_list(Token::RBracket)?;
+                self.eat(Token::RBracket)?;
+                Ok(Pattern::Vector(patterns))
+            },
+
+            _ => {
+                let literal = self.literal_pattern()?;
+
+                match self.cur_token_clone() {
+                    Some(operator @ (Token::DotDot | Token::DotDotEq)) => {
+                        self.next();
+                        let end = self.literal_pattern()?;
+                        if !literal.is_number() || !end.is_number() {
+                            return Err(LimError::parse("Range patterns must be bounded by numbers!", start));
+                        }
+                        Ok(Pattern::Range { start: literal, end, inclusive: operator == Token::DotDotEq })
+                    },
+                    _ => Ok(Pattern::Literal(literal)),
+                }
+            },
+        }
+    }
 
-        list.push(self.expression()?);
-        while self.cur_token_unwrap() == Token::Comma {
+    /// A literal in a pattern, which may be a negated number.
+    fn literal_pattern(&mut self) -> Result<Value, LimError> {
+        let start = self.cur_span();
+        let negative = self.cur_token_equals(Token::Minus);
+        if negative {
             self.next();
-            list.push(self.expression()?);
         }
-        
-        Ok(Box::new(list))
+
+        let token = self.cur_token_unwrap();
+        let value = match token {
+            Token::Int(_) | Token::BigInt(_) | Token::Rational(_) | Token::Float(_) => token.to_value(),
+            Token::String(_) | Token::True | Token::False | Token::Null if !negative => token.to_value(),
+            _ => return Err(LimError::parse(format!("Expected a pattern, found: {}!", token), self.cur_span())),
+        }.map_err(|e| e.at(start))?;
+        self.next();
+
+        match negative {
+            true => value.unary(&Token::Minus).map_err(|e| e.at(start)),
+            false => Ok(value),
+        }
+    }
+
+    /// Comma-separated patterns up to `end`, like `collect_list`. One of them may be a rest
+    /// pattern, `..` or `..name`.
+    fn pattern_list(&mut self, end: Token) -> Result<(Vec<Pattern>, bool), LimError> {
+        let mut patterns = vec![];
+        let mut trailing_comma = false;
+        let mut rest = false;
+
+        while !self.cur_token_equals(end.clone()) {
+            // `...name` is accepted as well as `..name`.
+            if matches!(self.cur_token(), Some(Token::DotDot | Token::Ellipsis)) {
+                if rest {
+                    return Err(LimError::parse("Only one rest pattern is allowed in a tuple or vector!", self.cur_span()));
+                }
+                rest = true;
+                self.next();
+
+                let name = match self.cur_token_clone() {
+                    Some(Token::Identifier(name)) => {
+                        self.next();
+                        Some(name)
+                    },
+                    _ => None,
+                };
+                patterns.push(Pattern::Rest { name, slot: None });
+            } else {
+                patterns.push(self.pattern()?);
+            }
+
+            trailing_comma = self.cur_token_equals(Token::Comma);
+            if trailing_comma {
+                self.next();
+            } else {
+                break;
+            }
+        }
+
+        Ok((patterns, trailing_comma))
     }
 }
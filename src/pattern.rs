@@ -0,0 +1,140 @@
+use std::cell::RefCell;
+
+use serde::{Serialize, Deserialize};
+
+use crate::ast_node::Slot;
+use crate::environment::Environment;
+use crate::error::LimError;
+use crate::resolver::resolved;
+use crate::token::Token;
+use crate::value::Value;
+
+/// What the arm of a `match` tests a value against, or what a destructuring `let`,
+/// assignment or parameter takes it apart with. Matching is the same in both backends:
+/// `matches` decides, and the caller stores the bindings it collected, see `destructure`.
+/// A `match` arm or `let` declares them in the order they appear; an assignment looks
+/// each name up.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Pattern {
+    /// `_`, which matches anything.
+    Wildcard,
+    /// A name, which matches anything and binds it.
+    Binding {
+        name: String,
+        slot: Option<Slot>,
+    },
+    /// A literal, which matches an equal value. Numbers compare by value, as with `==`.
+    Literal(Value),
+    /// `start..end` or `start..=end`, which matches the numbers in the range.
+    Range {
+        start: Value,
+        end: Value,
+        inclusive: bool,
+    },
+    Tuple(Vec<Pattern>),
+    Vector(Vec<Pattern>),
+    /// `..` or `..name` in a tuple or vector pattern: any number of items, which `..name`
+    /// binds as a tuple or vector.
+    Rest {
+        name: Option<String>,
+        slot: Option<Slot>,
+    },
+}
+
+impl Pattern {
+    /// Whether `value` matches, adding what the pattern binds to `bindings` if it does.
+    pub fn matches<'p>(&'p self, value: &Value, bindings: &mut Bindings<'p>) -> Result<bool, LimError> {
+        match (self, value) {
+            (Pattern::Wildcard, _) => Ok(true),
+            (Pattern::Binding { name, slot }, _) => {
+                bindings.push((name, resolved(slot, name)?, value.clone()));
+                Ok(true)
+            },
+            (Pattern::Literal(literal), _) if literal.is_number() && value.is_number() => Ok(value.equal(literal.clone()).unwrap_or(false)),
+            (Pattern::Literal(literal), _) => Ok(value == literal),
+            (Pattern::Range { start, end, inclusive }, _) => {
+                let below = if *inclusive { Token::LessEqual } else { Token::Less };
+                Ok(value.is_number()
+                    && value.relation(&Token::GreaterEqual, start).unwrap_or(false)
+                    && value.relation(&below, end).unwrap_or(false))
+            },
+            (Pattern::Tuple(patterns), Value::Tuple(items)) => sequence(patterns, items, Value::Tuple, bindings),
+            (Pattern::Vector(patterns), Value::Vector(items)) => sequence(patterns, items, Value::Vector, bindings),
+            (Pattern::Tuple(_) | Pattern::Vector(_), _) => Ok(false),
+            (Pattern::Rest { .. }, _) => Err(LimError::runtime("A rest pattern can only be used in a tuple or vector pattern")),
+        }
+    }
+
+    /// Matches `value` and stores what the pattern binds in `environment`: defined in its
+    /// innermost scope for a `let`, otherwise assigned to the variables already there.
+    /// Nothing is stored unless the whole value matches.
+    pub fn destructure(&self, value: &Value, environment: &RefCell<Environment>, define: bool) -> Result<(), LimError> {
+        let mut bindings = vec![];
+        if !self.matches(value, &mut bindings)? {
+            return Err(LimError::mismatch(value));
+        }
+
+        let mut environment = environment.borrow_mut();
+        for (name, slot, value) in bindings {
+            match define {
+                true => environment.define(slot.index, value),
+                false => environment.set(name, slot, value)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// The names the pattern binds and their slots, in order.
+    pub fn bindings(&mut self) -> Vec<(&mut String, &mut Option<Slot>)> {
+        match self {
+            Pattern::Binding { name, slot } | Pattern::Rest { name: Some(name), slot } => vec![(name, slot)],
+            Pattern::Tuple(patterns) | Pattern::Vector(patterns) => patterns.iter_mut().flat_map(Pattern::bindings).collect(),
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Range { .. } | Pattern::Rest { name: None, .. } => vec![],
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            Pattern::Binding { name, .. } | Pattern::Rest { name: Some(name), .. } => vec![name],
+            Pattern::Tuple(patterns) | Pattern::Vector(patterns) => patterns.iter().flat_map(Pattern::names).collect(),
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Range { .. } | Pattern::Rest { name: None, .. } => vec![],
+        }
+    }
+}
+
+/// What a match binds: each name, the slot it was resolved to and its value.
+pub type Bindings<'p> = Vec<(&'p str, Slot, Value)>;
+
+/// Matches the items of a tuple or vector, one pattern each except for a rest pattern,
+/// which takes the items the others leave over and binds them as `rest` makes them.
+fn sequence<'p>(patterns: &'p [Pattern], items: &[Value], rest: fn(Vec<Value>) -> Value, bindings: &mut Bindings<'p>) -> Result<bool, LimError> {
+    let Some(position) = patterns.iter().position(|pattern| matches!(pattern, Pattern::Rest { .. })) else {
+        if patterns.len() != items.len() {
+            return Ok(false);
+        }
+        return all(patterns, items, bindings);
+    };
+
+    if items.len() < patterns.len() - 1 {
+        return Ok(false);
+    }
+
+    // The patterns after the rest match the last items.
+    let end = items.len() - (patterns.len() - position - 1);
+    if !all(&patterns[..position], &items[..position], bindings)? {
+        return Ok(false);
+    }
+    if let Pattern::Rest { name: Some(name), slot } = &patterns[position] {
+        bindings.push((name, resolved(slot, name)?, rest(items[position..end].to_vec())));
+    }
+    all(&patterns[position + 1..], &items[end..], bindings)
+}
+
+fn all<'p>(patterns: &'p [Pattern], items: &[Value], bindings: &mut Bindings<'p>) -> Result<bool, LimError> {
+    for (pattern, item) in patterns.iter().zip(items) {
+        if !pattern.matches(item, bindings)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+
+use crate::ast_node::{ASTNode, ASTKind, Slot};
+use crate::error::LimError;
+use crate::pattern::Pattern;
+use crate::span::Span;
+
+/// The variables of one local scope and the slots they were given.
+#[derive(Default, Debug)]
+struct Scope {
+    names: HashMap<String, usize>,
+    size: usize,
+}
+
+/// Runs between parsing and evaluation. It numbers the variables of every scope and
+/// records on each `Identifier`, `Assignment` and declaration the `Slot` the variable is
+/// found at, so the interpreter indexes into frames instead of searching them by name.
+/// Use of an undefined variable is reported here, before anything runs.
+///
+/// The scopes it opens mirror the ones the interpreter creates at runtime: one per
+/// block, per `if` branch and loop body, and one per call holding the parameters (and
+/// `self`) with the function body's block nested inside it. A `for` loop's variable and
+/// the bindings of a `match` arm likewise get a scope of their own around the body's.
+#[derive(Default, Debug)]
+pub struct Resolver {
+    /// Global names and slots, kept from one program to the next so that REPL lines and
+    /// builtins stay visible.
+    globals: HashMap<String, usize>,
+    /// Globals used inside a function before their declaration. They must be declared
+    /// by the end of the program.
+    pending: Vec<(String, Span)>,
+    scopes: Vec<Scope>,
+    functions: usize,
+    /// The labels of the loops around the current node, within its function.
+    labels: Vec<Option<String>>,
+    /// Lets `let` and `fn` replace a variable of the same scope instead of failing.
+    pub redeclare: bool,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn resolve(&mut self, program: &mut ASTNode) -> Result<(), LimError> {
+        let globals = self.globals.clone();
+
+        let result = match &mut program.kind {
+            ASTKind::Block { statements } => self.statements(statements),
+            _ => self.node(program),
+        }.and_then(|_| match self.pending.first() {
+            Some((name, span)) => Err(LimError::name(format!("Undefined variable: {}", name)).at(*span)),
+            None => Ok(()),
+        });
+
+        if result.is_err() {
+            self.globals = globals;
+        }
+        self.pending.clear();
+        self.scopes.clear();
+        self.functions = 0;
+        self.labels.clear();
+
+        result
+    }
+
+    /// Gives a global its slot, for builtins defined before any program runs.
+    pub fn define_global(&mut self, name: &str) -> Result<usize, LimError> {
+        self.declare(name).map(|slot| slot.index)
+    }
+
+    fn statements(&mut self, statements: &mut [ASTNode]) -> Result<(), LimError> {
+        // Functions are declared before the block runs, so they can call each other.
+        for statement in statements.iter_mut() {
+            if let ASTKind::FunctionDeclaration { name, slot, .. } = &mut statement.kind {
+                *slot = Some(self.declare(name).map_err(|e| e.at(statement.span))?);
+            }
+        }
+
+        for statement in statements.iter_mut() {
+            match &mut statement.kind {
+                ASTKind::FunctionDeclaration { params, body, .. } => self.function(params, body)?,
+                _ => self.node(statement)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn scoped(&mut self, node: &mut ASTNode) -> Result<(), LimError> {
+        self.scopes.push(Scope::default());
+
+        let result = match &mut node.kind {
+            ASTKind::Block { statements } => self.statements(statements),
+            _ => self.node(node),
+        };
+
+        self.scopes.pop();
+        result
+    }
+
+    fn function(&mut self, params: &[String], body: &mut ASTNode) -> Result<(), LimError> {
+        self.scopes.push(Scope::default());
+        self.functions += 1;
+        // `break` and `continue` cannot reach the loops around a function.
+        let labels = std::mem::take(&mut self.labels);
+
+        let result = params.iter()
+            .map(String::as_str)
+            .chain(["self"])
+            .try_for_each(|name| self.declare(name).map(|_| ()).map_err(|e| e.at(body.span)))
+            .and_then(|_| self.node(body));
+
+        self.labels = labels;
+        self.functions -= 1;
+        self.scopes.pop();
+        result
+    }
+
+    fn loop_body(&mut self, label: &Option<String>, body: &mut ASTNode) -> Result<(), LimError> {
+        self.labels.push(label.clone());
+        let result = self.scoped(body);
+        self.labels.pop();
+        result
+    }
+
+    fn check_label(&self, label: &Option<String>, span: Span) -> Result<(), LimError> {
+        match label {
+            Some(name) if !self.labels.contains(label) => Err(LimError::name(format!("Undeclared label: '{}", name)).at(span)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Declares the variables a pattern binds in the innermost scope, in order.
+    fn pattern(&mut self, pattern: &mut Pattern, span: Span) -> Result<(), LimError> {
+        for (name, slot) in pattern.bindings() {
+            *slot = Some(self.declare(name).map_err(|e| e.at(span))?);
+        }
+        Ok(())
+    }
+
+    /// Declares `name` in the innermost scope, returning its slot.
+    fn declare(&mut self, name: &str) -> Result<Slot, LimError> {
+        let redeclare = self.redeclare;
+        let redeclared = || LimError::name(format!("Variable {} have been declared!", name));
+
+        let Some(scope) = self.scopes.last_mut() else {
+            if let Some(position) = self.pending.iter().position(|(pending, _)| pending == name) {
+                self.pending.remove(position);
+            } else if self.globals.contains_key(name) && !redeclare {
+                return Err(redeclared());
+            }
+
+            let count = self.globals.len();
+            let index = *self.globals.entry(name.to_string()).or_insert(count);
+            return Ok(Slot { depth: 0, index });
+        };
+
+        if scope.names.contains_key(name) && !redeclare {
+            return Err(redeclared());
+        }
+
+        let index = scope.size;
+        scope.size += 1;
+        scope.names.insert(name.to_string(), index);
+        Ok(Slot { depth: 0, index })
+    }
+
+    fn lookup(&mut self, name: &str, span: Span) -> Result<Slot, LimError> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(&index) = scope.names.get(name) {
+                return Ok(Slot { depth, index });
+            }
+        }
+
+        let depth = self.scopes.len();
+        if let Some(&index) = self.globals.get(name) {
+            return Ok(Slot { depth, index });
+        }
+
+        // A function body only runs once it is called, by which time a global declared
+        // further down may exist.
+        if self.functions > 0 {
+            let index = self.globals.len();
+            self.globals.insert(name.to_string(), index);
+            self.pending.push((name.to_string(), span));
+            return Ok(Slot { depth, index });
+        }
+
+        Err(LimError::name(format!("Undefined variable: {}", name)).at(span))
+    }
+
+    fn node(&mut self, node: &mut ASTNode) -> Result<(), LimError> {
+        let span = node.span;
+
+        match &mut node.kind {
+            ASTKind::Block { .. } => self.scoped(node)?,
+
+            ASTKind::FunctionDeclaration { name, params, body, slot } => {
+                *slot = Some(self.declare(name).map_err(|e| e.at(span))?);
+                self.function(params, body)?;
+            },
+
+            ASTKind::FunctionDefinition { params, body } => self.function(params, body)?,
+
+            ASTKind::FunctionCall { function, arguments } => {
+                self.node(function)?;
+                for argument in arguments {
+                    self.node(argument)?;
+                }
+            },
+
+            ASTKind::BinaryOperation { left, right, .. } | ASTKind::LogicalOperation { left, right, .. } => {
+                self.node(left)?;
+                self.node(right)?;
+            },
+
+            ASTKind::UnaryOperation { operand, .. } => self.node(operand)?,
+
+            ASTKind::Let { ast } => {
+                let span = ast.span;
+                match &mut ast.kind {
+                    ASTKind::Identifier { name, slot } => {
+                        *slot = Some(self.declare(name).map_err(|e| e.at(span))?);
+                    },
+                    ASTKind::Assignment { name, value, slot } => {
+                        // The value is resolved first, so `let x = x + 1` reads an outer `x`.
+                        self.node(value)?;
+                        *slot = Some(self.declare(name).map_err(|e| e.at(span))?);
+                    },
+                    ASTKind::Destructure { pattern, value } => {
+                        self.node(value)?;
+                        self.pattern(pattern, span)?;
+                    },
+                    _ => (),
+                }
+            },
+
+            ASTKind::IndexAssignment { name, indices, value, slot, .. } => {
+                for index in indices {
+                    self.node(index)?;
+                }
+                self.node(value)?;
+                *slot = Some(self.lookup(name, span)?);
+            },
+
+            ASTKind::Destructure { pattern, value } => {
+                self.node(value)?;
+                for (name, slot) in pattern.bindings() {
+                    *slot = Some(self.lookup(name, span)?);
+                }
+            },
+
+            ASTKind::Assignment { name, value, slot } => {
+                self.node(value)?;
+                *slot = Some(self.lookup(name, span)?);
+            },
+
+            ASTKind::Identifier { name, slot } => {
+                *slot = Some(self.lookup(name, span)?);
+            },
+
+            ASTKind::Conditional { condition, true_branch, false_branch } => {
+                self.node(condition)?;
+                self.scoped(true_branch)?;
+                if let Some(false_branch) = false_branch {
+                    self.scoped(false_branch)?;
+                }
+            },
+
+            ASTKind::Loop { condition, body, label } => {
+                self.node(condition)?;
+                self.loop_body(label, body)?;
+            },
+
+            // Each iteration's scope holds the variable, with the body's scope inside it.
+            ASTKind::For { variable, iterable, body, slot, label } => {
+                self.node(iterable)?;
+                self.scopes.push(Scope::default());
+                let result = self.declare(variable).map_err(|e| e.at(span)).and_then(|declared| {
+                    *slot = Some(declared);
+                    self.loop_body(label, body)
+                });
+                self.scopes.pop();
+                result?;
+            },
+
+            // Each arm's scope holds its bindings, with the body's scope inside it.
+            ASTKind::Match { scrutinee, arms } => {
+                self.node(scrutinee)?;
+
+                for arm in arms {
+                    self.scopes.push(Scope::default());
+                    let result = self.pattern(&mut arm.pattern, arm.body.span)
+                        .and_then(|_| arm.guard.as_mut().map_or(Ok(()), |guard| self.node(guard)))
+                        .and_then(|_| self.scoped(&mut arm.body));
+                    self.scopes.pop();
+                    result?;
+                }
+            },
+
+            ASTKind::Interpolation(nodes) | ASTKind::Tuple(nodes) | ASTKind::Vector(nodes) => {
+                for node in nodes {
+                    self.node(node)?;
+                }
+            },
+
+            ASTKind::Index { expression, index } => {
+                self.node(expression)?;
+                self.node(index)?;
+            },
+
+            ASTKind::Return(value) => self.node(value)?,
+
+            ASTKind::Break { label, value } => {
+                self.check_label(label, span)?;
+                if let Some(value) = value {
+                    self.node(value)?;
+                }
+            },
+
+            ASTKind::Continue { label } => self.check_label(label, span)?,
+
+            ASTKind::Literal(_) => (),
+        }
+
+        Ok(())
+    }
+}
+
+/// Takes the slot the resolver recorded, for nodes evaluated without being resolved.
+pub fn resolved(slot: &Option<Slot>, name: &str) -> Result<Slot, LimError> {
+    slot.ok_or_else(|| LimError::runtime(format!("Unresolved variable: {}", name)))
+}
+
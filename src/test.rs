@@ -1,9 +1,18 @@
 #[cfg(test)]
 mod tests {
-    use crate::interpreter::Interpreter;
+    use crate::interpreter::{Interpreter, MAX_DEPTH, stack_size};
     use crate::value::Value;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::token::Token;
+    use crate::ast_node::{ASTNode, ASTKind, Slot};
+    use crate::resolver::Resolver;
+    use crate::vm::Vm;
+    use crate::bytecode::Program;
+    use crate::span::Span;
+    use crate::error::{LimError, ErrorKind};
 
-    fn interpret(text: &str) -> Result<Value, String> {
+    fn interpret(text: &str) -> Result<Value, LimError> {
         let mut interpreter = Interpreter::new();
         interpreter.interpret(text.to_string())
     }
@@ -14,36 +23,36 @@ mod tests {
 
     #[test]
     fn test_arithmetic_operations() {
-        assert_eq!(interpret("3").unwrap(), Value::Number(3.0));
-        assert_eq!(interpret("2 + 7 * 4").unwrap(), Value::Number(30.0));
-        assert_eq!(interpret("7 - 8 / 4").unwrap(), Value::Number(5.0));
-        assert_eq!(interpret("14 + 2 * 3 - 6 / 2").unwrap(), Value::Number(17.0));
-        assert_eq!(interpret("10 % 3").unwrap(), Value::Number(1.0));
-        assert_eq!(interpret("22 % 5").unwrap(), Value::Number(2.0));
+        assert_eq!(interpret("3").unwrap(), Value::Int(3));
+        assert_eq!(interpret("2 + 7 * 4").unwrap(), Value::Int(30));
+        assert_eq!(interpret("7 - 8 / 4").unwrap(), Value::Int(5));
+        assert_eq!(interpret("14 + 2 * 3 - 6 / 2").unwrap(), Value::Int(17));
+        assert_eq!(interpret("10 % 3").unwrap(), Value::Int(1));
+        assert_eq!(interpret("22 % 5").unwrap(), Value::Int(2));
         assert_float_eq(interpret("0.1 + 0.2").unwrap().to_number().unwrap(), 0.3);
     }
 
     #[test]
     fn test_complex_expressions() {
-        assert_eq!(interpret("7 + 3 * (10 / (12 / (3 + 1) - 1))").unwrap(), Value::Number(22.0));
-        assert_eq!(interpret("7 + 3 * (10 / (12 / (3 + 1) - 1)) / (2 + 3) - 5 - 3 + (8)").unwrap(), Value::Number(10.0));
-        assert_eq!(interpret("7 + (((3 + 2)))").unwrap(), Value::Number(12.0));
+        assert_eq!(interpret("7 + 3 * (10 / (12 / (3 + 1) - 1))").unwrap(), Value::Int(22));
+        assert_eq!(interpret("7 + 3 * (10 / (12 / (3 + 1) - 1)) / (2 + 3) - 5 - 3 + (8)").unwrap(), Value::Int(10));
+        assert_eq!(interpret("7 + (((3 + 2)))").unwrap(), Value::Int(12));
     }
 
     #[test]
     fn test_unary_operations() {
-        assert_eq!(interpret("-5 + 3").unwrap(), Value::Number(-2.0));
-        assert_eq!(interpret("-5").unwrap(), Value::Number(-5.0));
-        assert_eq!(interpret("3 - (-2)").unwrap(), Value::Number(5.0));
+        assert_eq!(interpret("-5 + 3").unwrap(), Value::Int(-2));
+        assert_eq!(interpret("-5").unwrap(), Value::Int(-5));
+        assert_eq!(interpret("3 - (-2)").unwrap(), Value::Int(5));
     }
 
     #[test]
     fn test_variable_operations() {
-        assert_eq!(interpret("let x = 5; x").unwrap(), Value::Number(5.0));
-        assert_eq!(interpret("let x = 5; x + 3").unwrap(), Value::Number(8.0));
-        assert_eq!(interpret("let x = 5; let y = 3; x * y").unwrap(), Value::Number(15.0));
-        assert_eq!(interpret("let x = 5; let y = 3; let z = x + y; z * 2").unwrap(), Value::Number(16.0));
-        assert_eq!(interpret("let x = 5; x = 10; x").unwrap(), Value::Number(10.0));
+        assert_eq!(interpret("let x = 5; x").unwrap(), Value::Int(5));
+        assert_eq!(interpret("let x = 5; x + 3").unwrap(), Value::Int(8));
+        assert_eq!(interpret("let x = 5; let y = 3; x * y").unwrap(), Value::Int(15));
+        assert_eq!(interpret("let x = 5; let y = 3; let z = x + y; z * 2").unwrap(), Value::Int(16));
+        assert_eq!(interpret("let x = 5; x = 10; x").unwrap(), Value::Int(10));
     }
 
     #[test]
@@ -83,14 +92,14 @@ mod tests {
 
     #[test]
     fn test_multiple_statements() {
-        assert_eq!(interpret("let x = 5;let y = 10; x + y").unwrap(), Value::Number(15.0));
-        assert_eq!(interpret("let x = 3; x = x * 2; x + 1").unwrap(), Value::Number(7.0));
+        assert_eq!(interpret("let x = 5;let y = 10; x + y").unwrap(), Value::Int(15));
+        assert_eq!(interpret("let x = 3; x = x * 2; x + 1").unwrap(), Value::Int(7));
     }
 
     #[test]
     fn test_precedence() {
-        assert_eq!(interpret("2 + 3 * 4").unwrap(), Value::Number(14.0));
-        assert_eq!(interpret("(2 + 3) * 4").unwrap(), Value::Number(20.0));
+        assert_eq!(interpret("2 + 3 * 4").unwrap(), Value::Int(14));
+        assert_eq!(interpret("(2 + 3) * 4").unwrap(), Value::Int(20));
     }
 
     #[test]
@@ -102,10 +111,9 @@ mod tests {
             };
             add(3, 4)
         "#;
-        assert_eq!(interpret(program).unwrap(), Value::Number(7.0));
+        assert_eq!(interpret(program).unwrap(), Value::Int(7));
     }
 
-    /*
     #[test]
     fn test_recursive_function() {
         let program = r#"
@@ -118,9 +126,38 @@ mod tests {
             }
             factorial(5)
         "#;
-        assert_eq!(interpret(program).unwrap(), Value::Number(120.0));
+        assert_eq!(interpret(program).unwrap(), Value::Int(120));
+    }
+
+    #[test]
+    fn test_function_declarations() {
+        let program = r#"
+            let result = is_even(10);
+            fn is_even(n) { if n == 0 { true } else { is_odd(n - 1) } }
+            fn is_odd(n) { if n == 0 { false } else { is_even(n - 1) } }
+            result && is_odd(7)
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Boolean(true));
+
+        let program = r#"
+            fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }
+            fib(15)
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(610));
+
+        let program = r#"
+            fn outer(x) {
+                fn helper(y) { y * scale }
+                helper(x) + 1
+            }
+            let scale = 10;
+            outer(4)
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(41));
+
+        assert_eq!(interpret("let f = 1; fn f(x) { 2 }").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(interpret("fn f(x) { x }; let f = 1").unwrap_err().kind, ErrorKind::Name);
     }
-    */
     
     
     #[test]
@@ -136,6 +173,1036 @@ mod tests {
             add5 = make_adder(5);
             add5(3)
         "#;
-        assert_eq!(interpret(program).unwrap(), Value::Number(8.0));
+        assert_eq!(interpret(program).unwrap(), Value::Int(8));
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let tokens = Lexer::tokenize("let x = 5;\n  x + 10".to_string()).unwrap();
+        assert_eq!(tokens[0].node, Token::Let);
+        assert_eq!(tokens[0].span, Span::new(0, 3, 1, 1));
+        assert_eq!(tokens[5].node, Token::Identifier("x".to_string()));
+        assert_eq!(tokens[5].span, Span::new(13, 14, 2, 3));
+        assert_eq!(tokens[7].span, Span::new(17, 19, 2, 7));
+    }
+
+    #[test]
+    fn test_node_spans() {
+        let ast = Parser::new("1 + 2;\nfoo(3, 4)".to_string()).unwrap().parse().unwrap();
+        assert_eq!(ast.span, Span::new(0, 16, 1, 1));
+        if let ASTKind::Block { statements } = ast.kind {
+            assert_eq!(statements[0].span, Span::new(0, 5, 1, 1));
+            assert_eq!(statements[1].span, Span::new(7, 16, 2, 1));
+        } else {
+            panic!("Expected block");
+        }
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let mut interpreter = Interpreter::new();
+        let error = interpreter.interpret("let x = 1;\nx + y".to_string()).unwrap_err();
+        assert_eq!(error.span, Some(Span::new(15, 16, 2, 5)));
+
+        let error = interpreter.interpret("(1 + 2".to_string()).unwrap_err();
+        assert_eq!(error.span.map(|span| (span.line, span.column)), Some((1, 7)));
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        assert_eq!(interpret("a + 5").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(interpret("10 / (5 - 5)").unwrap_err().kind, ErrorKind::Division);
+        assert_eq!(interpret("10 *").unwrap_err().kind, ErrorKind::Parse);
+        assert_eq!(interpret("\"\\q\"").unwrap_err().kind, ErrorKind::Lex);
+        assert_eq!(interpret("1 + true").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(interpret("(1, 2)[5]").unwrap_err().kind, ErrorKind::Index);
+        assert_eq!(interpret("let f = fn (a) { a }; f(1, 2)").unwrap_err().kind, ErrorKind::Arity);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.init().unwrap();
+        let error = interpreter.interpret("throw(\"bad\", 42)".to_string()).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Thrown);
+        assert_eq!(error.message, "bad 42");
+    }
+
+    #[test]
+    fn test_error_report() {
+        let error = interpret("let x = 1;\nx + y").unwrap_err();
+        assert_eq!(error.report("let x = 1;\nx + y"), "name error: Undefined variable: y at 2:5\n 2 | x + y\n   |     ^");
+    }
+
+    #[test]
+    fn test_lexer_errors_do_not_panic() {
+        for text in ["1.2.3", "1 && & 2", "@", "\"abc", "let f = fn (1) { 2 }"] {
+            assert!(interpret(text).is_err(), "{}", text);
+        }
+
+        let (tokens, errors) = Lexer::tokenize_all("1 @ 2 # 3");
+        assert_eq!(tokens.iter().map(|token| token.node.clone()).collect::<Vec<_>>(), vec![Token::Int(1), Token::Int(2), Token::Int(3), Token::Eof]);
+        assert_eq!(errors.iter().map(|error| error.span.unwrap().column).collect::<Vec<_>>(), vec![3, 7]);
+    }
+
+    #[test]
+    fn test_comments() {
+        let program = r#"#!/usr/bin/env lim
+            // the answer
+            let x = 40; // inline
+            /* block /* nested */ still a comment */
+            x + /* between */ 2
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(42));
+        assert_eq!(interpret("6 / 3 // half").unwrap(), Value::Int(2));
+        assert_eq!(interpret("1 /* never closed").unwrap_err().kind, ErrorKind::Lex);
+        assert_eq!(interpret("1;\n#!not a shebang").unwrap_err().kind, ErrorKind::Lex);
+    }
+
+    fn string(text: &str) -> String {
+        match interpret(text).unwrap() {
+            Value::String(string) => string,
+            value => panic!("Expected string, found: {}", value),
+        }
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(string(r#""a\tb\n""#), "a\tb\n");
+        assert_eq!(string(r#""\u{1F600}""#), "\u{1F600}");
+        assert_eq!(string(r#""\u00e9\u4E2D""#), "é中");
+        assert_eq!(string(r#""\uD83D\uDE00""#), "\u{1F600}");
+        assert_eq!(interpret(r#""\uD83D""#).unwrap_err().kind, ErrorKind::Lex);
+        assert_eq!(interpret(r#""\uDE00""#).unwrap_err().kind, ErrorKind::Lex);
+        assert_eq!(interpret(r#""\u{110000}""#).unwrap_err().kind, ErrorKind::Lex);
+        assert_eq!(interpret(r#""\u12""#).unwrap_err().kind, ErrorKind::Lex);
+        assert_eq!(interpret("\"line\nbreak\"").unwrap_err().kind, ErrorKind::Lex);
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        assert_eq!(string(r#"r"C:\Users\lim""#), r"C:\Users\lim");
+        assert_eq!(string(r###"r#"say "hi" \d+"#"###), r#"say "hi" \d+"#);
+        assert_eq!(string(r###"r##"a "# b"##"###), r##"a "# b"##);
+        assert_eq!(interpret(r#"r#"open""#).unwrap_err().kind, ErrorKind::Lex);
+    }
+
+    #[test]
+    fn test_multiline_strings() {
+        let program = "\"\"\"\n        SELECT *\n          FROM t\n        WHERE x = \\\"1\\\"\n        \"\"\"";
+        assert_eq!(string(program), "SELECT *\n  FROM t\nWHERE x = \"1\"");
+        assert_eq!(string("\"\"\"one \"quoted\" line\"\"\""), "one \"quoted\" line");
+        assert_eq!(interpret("\"\"\"never closed").unwrap_err().kind, ErrorKind::Lex);
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        assert_eq!(string(r#"let a = 1; let b = 2; "total: ${a + b}!""#), "total: 3!");
+        assert_eq!(string(r#"let v = [1, 2]; "${v} has ${v[0]} first""#), "[1, 2] has 1 first");
+        assert_eq!(string(r#""outer ${"inner ${1 + 1}"} done""#), "outer inner 2 done");
+        assert_eq!(string(r#""${ 1 + 3 }${true}""#), "4true");
+        assert_eq!(string(r#""cost: \${x}""#), "cost: ${x}");
+        assert_eq!(interpret(r#""${}""#).unwrap_err().kind, ErrorKind::Parse);
+        assert_eq!(interpret(r#""${1 2}""#).unwrap_err().kind, ErrorKind::Parse);
+        assert_eq!(interpret(r#""${1 + 2""#).unwrap_err().kind, ErrorKind::Lex);
+        assert_eq!(interpret(r#""${missing}""#).unwrap_err().kind, ErrorKind::Name);
+    }
+
+    #[test]
+    fn test_numeric_literals() {
+        assert_eq!(interpret("0xFF").unwrap(), Value::Int(255));
+        assert_eq!(interpret("0b1010").unwrap(), Value::Int(10));
+        assert_eq!(interpret("0o755").unwrap(), Value::Int(493));
+        assert_eq!(interpret("1_000_000").unwrap(), Value::Int(1_000_000));
+        assert_eq!(interpret("0xdead_BEEF").unwrap(), Value::Int(3735928559));
+        assert_eq!(interpret("1e5").unwrap(), Value::Number(1e5));
+        assert_eq!(interpret("1e-9").unwrap(), Value::Number(1e-9));
+        assert_eq!(interpret("6.02E23").unwrap(), Value::Number(6.02e23));
+        assert_eq!(interpret("2.5e+2").unwrap(), Value::Number(250.0));
+        assert_eq!(interpret(".5").unwrap(), Value::Number(0.5));
+
+        for text in ["1.2.3", "0x", "0xFG", "0b102", "1e", "1e+", "12abc", "1.5rx"] {
+            assert_eq!(interpret(text).unwrap_err().kind, ErrorKind::Lex, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_integers() {
+        assert_eq!(interpret("9007199254740992 + 1").unwrap(), Value::Int(9007199254740993));
+        assert_eq!(interpret("9007199254740993 > 9007199254740992").unwrap(), Value::Boolean(true));
+        assert_eq!(interpret("7 / 2").unwrap(), Value::Int(3));
+        assert_eq!(interpret("-7 / 2").unwrap(), Value::Int(-3));
+        assert_eq!(interpret("-7 % 3").unwrap(), Value::Int(-1));
+        assert_eq!(interpret("7.0 / 2").unwrap(), Value::Number(3.5));
+        assert_eq!(interpret("1 + 0.5").unwrap(), Value::Number(1.5));
+        assert_eq!(interpret("2 == 2.0").unwrap(), Value::Boolean(true));
+        assert_eq!(interpret("1234567890123456789 % 1000").unwrap(), Value::Int(789));
+        assert_eq!(interpret("1 / 0").unwrap_err().kind, ErrorKind::Division);
+    }
+
+    #[test]
+    fn test_indexing() {
+        assert_eq!(interpret("[10, 20, 30][1]").unwrap(), Value::Int(20));
+        assert_eq!(interpret("[10, 20, 30][2.0]").unwrap(), Value::Int(30));
+        assert_eq!(interpret("[10, 20, 30][1.5]").unwrap_err().kind, ErrorKind::Index);
+        assert_eq!(interpret("[10, 20, 30][-1]").unwrap_err().kind, ErrorKind::Index);
+        assert_eq!(interpret("[10, 20, 30][3]").unwrap_err().kind, ErrorKind::Index);
+        assert_eq!(interpret("[10, 20, 30][true]").unwrap_err().kind, ErrorKind::Type);
+    }
+
+    fn big(text: &str) -> Value {
+        Value::BigInt(text.parse().unwrap())
+    }
+
+    fn ratio(numerator: i64, denominator: i64) -> Value {
+        Value::Rational(num_rational::BigRational::new(numerator.into(), denominator.into()))
+    }
+
+    #[test]
+    fn test_big_integers() {
+        assert_eq!(interpret("9223372036854775807 + 1").unwrap(), big("9223372036854775808"));
+        assert_eq!(interpret("(0 - 9223372036854775807 - 1) / -1").unwrap(), big("9223372036854775808"));
+        assert_eq!(interpret("-(0 - 9223372036854775807 - 1)").unwrap(), big("9223372036854775808"));
+        assert_eq!(interpret("4294967296 * 4294967296 * 4294967296").unwrap(), big("79228162514264337593543950336"));
+        assert_eq!(interpret("0x1_0000_0000_0000_0000").unwrap(), big("18446744073709551616"));
+        assert_eq!(interpret("100000000000000000000 - 99999999999999999999").unwrap(), Value::Int(1));
+        assert_eq!(interpret("100000000000000000000 % 7").unwrap(), Value::Int(2));
+        assert_eq!(interpret("100000000000000000000 > 99999999999999999999").unwrap(), Value::Boolean(true));
+        assert_eq!(interpret("100000000000000000000 == 100000000000000000000").unwrap(), Value::Boolean(true));
+        assert_eq!(interpret("100000000000000000000 / 0").unwrap_err().kind, ErrorKind::Division);
+
+        let program = r#"
+            let n = 1; let i = 1;
+            while i <= 25 { n = n * i; i = i + 1; };
+            n
+        "#;
+        assert_eq!(interpret(program).unwrap(), big("15511210043330985984000000"));
+    }
+
+    #[test]
+    fn test_rationals() {
+        assert_eq!(interpret("0.1r + 0.2r").unwrap(), ratio(3, 10));
+        assert_eq!(interpret("0.1r + 0.2r == 0.3r").unwrap(), Value::Boolean(true));
+        assert_eq!(interpret("1.5e-3r").unwrap(), ratio(3, 2000));
+        assert_eq!(interpret("2r / 3 * 3").unwrap(), ratio(2, 1));
+        assert_eq!(interpret("1r / 3 > 0.33r").unwrap(), Value::Boolean(true));
+        assert_eq!(interpret("1r / 4 + 0.5").unwrap(), Value::Number(0.75));
+        assert_eq!(interpret("-(1r / 3)").unwrap(), ratio(-1, 3));
+        assert_eq!(interpret("[1, 2, 3][4r / 2]").unwrap(), Value::Int(3));
+        assert_eq!(interpret("1r / 0").unwrap_err().kind, ErrorKind::Division);
+        assert_eq!(interpret("1r / 3").unwrap().to_string(), "1/3");
+        assert_eq!(interpret("1e99999999999999999999r").unwrap_err().kind, ErrorKind::Lex);
+        assert_eq!(interpret("1e100000000r").unwrap_err().kind, ErrorKind::Lex);
+        assert_eq!(interpret("1e-100000000r").unwrap_err().kind, ErrorKind::Lex);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.init().unwrap();
+        assert_eq!(interpreter.interpret("rational(1, 3) + rational(1, 6)".to_string()).unwrap(), ratio(1, 2));
+        assert_eq!(interpreter.interpret("rational(0.5)".to_string()).unwrap(), ratio(1, 2));
+        assert_eq!(interpreter.interpret("rational(1, 0)".to_string()).unwrap_err().kind, ErrorKind::Division);
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        assert_eq!(interpret("12 & 10").unwrap(), Value::Int(8));
+        assert_eq!(interpret("12 | 10").unwrap(), Value::Int(14));
+        assert_eq!(interpret("12 ^ 10").unwrap(), Value::Int(6));
+        assert_eq!(interpret("~0").unwrap(), Value::Int(-1));
+        assert_eq!(interpret("~~5").unwrap(), Value::Int(5));
+        assert_eq!(interpret("1 << 4").unwrap(), Value::Int(16));
+        assert_eq!(interpret("-16 >> 2").unwrap(), Value::Int(-4));
+        assert_eq!(interpret("-1 >> 100").unwrap(), Value::Int(-1));
+        assert_eq!(interpret("1 << 64").unwrap(), big("18446744073709551616"));
+        assert_eq!(interpret("(1 << 64) >> 63").unwrap(), Value::Int(2));
+        assert_eq!(interpret("true ^ false").unwrap(), Value::Boolean(true));
+
+        // Shifts bind tighter than `&`, `&` tighter than `^`, `^` tighter than `|`,
+        // and all of them tighter than comparisons.
+        assert_eq!(interpret("1 | 2 ^ 3 & 4 << 1").unwrap(), Value::Int(3));
+        assert_eq!(interpret("1 << 2 + 1").unwrap(), Value::Int(8));
+        assert_eq!(interpret("6 & 3 == 2").unwrap(), Value::Boolean(true));
+        assert_eq!(interpret("(0xDEADBEEF ^ 0xFFFF) & 0xFF00 >> 8").unwrap(), Value::Int(16));
+
+        assert_eq!(interpret("1 << -1").unwrap_err().kind, ErrorKind::Runtime);
+        assert_eq!(interpret("1 << 100000000").unwrap_err().kind, ErrorKind::Overflow);
+        assert_eq!(interpret("1.5 & 1").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(interpret("~true").unwrap_err().kind, ErrorKind::Type);
+        assert!(Lexer::tokenize("a & b | c".to_string()).is_ok());
+    }
+
+    #[test]
+    fn test_power_operator() {
+        assert_eq!(interpret("2 ** 10").unwrap(), Value::Int(1024));
+        assert_eq!(interpret("2 ** 3 ** 2").unwrap(), Value::Int(512));
+        assert_eq!(interpret("-2 ** 2").unwrap(), Value::Int(-4));
+        assert_eq!(interpret("2 ** -2").unwrap(), ratio(1, 4));
+        assert_eq!(interpret("2 * 3 ** 2").unwrap(), Value::Int(18));
+        assert_eq!(interpret("(2r / 3) ** 2").unwrap(), ratio(4, 9));
+        assert_eq!(interpret("2 ** 100").unwrap(), big("1267650600228229401496703205376"));
+        assert_eq!(interpret("4 ** 0.5").unwrap(), Value::Number(2.0));
+        assert_eq!(interpret("(-1) ** 100000000001").unwrap(), Value::Int(-1));
+        assert_eq!(interpret("0 ** 0").unwrap(), Value::Int(1));
+        assert_eq!(interpret("0 ** -1").unwrap_err().kind, ErrorKind::Division);
+        assert_eq!(interpret("10 ** 100000000").unwrap_err().kind, ErrorKind::Overflow);
+    }
+
+    #[test]
+    fn test_call_expressions() {
+        let program = r#"
+            let make_adder = fn (x) { fn (y) { x + y } };
+            make_adder(5)(3)
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(8));
+
+        let program = r#"
+            let handlers = [fn (e) { e + 1 }, fn (e) { e * 10 }];
+            handlers[1](4) + handlers[0](4)
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(45));
+
+        assert_eq!(interpret("(fn (x) { x * 2 })(4)").unwrap(), Value::Int(8));
+        assert_eq!(interpret("let pick = fn (i) { [fn (x) { -x }][i] }; pick(0)(7) ** 2").unwrap(), Value::Int(49));
+        assert_eq!(interpret("let fns = [[fn (x) { x }]]; fns[0][0]([1, 2])[1]").unwrap(), Value::Int(2));
+
+        let error = interpret("let x = 1;\n[x](2)").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Type);
+        assert_eq!(error.span.unwrap().column, 1);
+        assert_eq!(interpret("missing(1)").unwrap_err().kind, ErrorKind::Name);
+    }
+
+    #[test]
+    fn test_empty_and_trailing_lists() {
+        assert_eq!(interpret("let f = fn () { 42 }; f()").unwrap(), Value::Int(42));
+        assert_eq!(interpret("fn answer() { 42 }\nanswer() + answer(,)").unwrap_err().kind, ErrorKind::Parse);
+        assert_eq!(interpret("fn answer() { 42 }\nanswer() + 1").unwrap(), Value::Int(43));
+        assert_eq!(interpret("[]").unwrap(), Value::Vector(vec![]));
+        assert_eq!(interpret("()").unwrap(), Value::Tuple(vec![]));
+        assert_eq!(interpret("(1,)").unwrap(), Value::Tuple(vec![Value::Int(1)]));
+        assert_eq!(interpret("(1)").unwrap(), Value::Int(1));
+        assert_eq!(interpret("(1, 2,)").unwrap(), Value::Tuple(vec![Value::Int(1), Value::Int(2)]));
+        assert_eq!(interpret("[1, 2,]").unwrap(), Value::Vector(vec![Value::Int(1), Value::Int(2)]));
+        assert_eq!(interpret("let add = fn (a, b,) { a + b }; add(1, 2,)").unwrap(), Value::Int(3));
+        assert_eq!(interpret("(1,)[0]").unwrap(), Value::Int(1));
+        assert_eq!(interpret("[[], ()]").unwrap().to_string(), "[[], ()]");
+        // A single item prints with its comma, so the output reads back as the same tuple.
+        assert_eq!(interpret("((1,), [(2,)], (3, 4))").unwrap().to_string(), "((1,), [(2,)], (3, 4))");
+        assert_eq!(interpret("(1,)").unwrap().to_string(), "(1,)");
+
+        for text in ["[,]", "(,)", "[1,,]", "f(1 2)"] {
+            assert_eq!(interpret(text).unwrap_err().kind, ErrorKind::Parse, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_block_expressions() {
+        assert_eq!(interpret("let c = false; let x = if c { 1 } else { 2 }; x").unwrap(), Value::Int(2));
+        assert_eq!(interpret("let f = fn (x) { x + 1 }; f({ let t = 3; t * t })").unwrap(), Value::Int(10));
+        assert_eq!(interpret("1 + if true { 2 } else { 3 } * 10").unwrap(), Value::Int(21));
+        assert_eq!(interpret("let x = if false { 1 }; x").unwrap(), Value::Null);
+        assert_eq!(interpret("let i = 0; let last = while i < 3 { i = i + 1; i * 10 }; last").unwrap(), Value::Int(30));
+        assert_eq!(interpret("[{ 1 }, if true { 2 } else { 0 }]").unwrap(), Value::Vector(vec![Value::Int(1), Value::Int(2)]));
+
+        // Block-like statements need no semicolon, and do not swallow what follows them.
+        let program = r#"
+            let x = 0;
+            if x == 0 { x = 1 } else { x = 2 }
+            { x = x * 10 }
+            (x)
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(10));
+
+        // `return` and `break` inside an expression leave the enclosing function or loop.
+        let program = r#"
+            let sign = fn (n) {
+                let label = if n < 0 { return -1 } else { "non-negative" };
+                label
+            };
+            [sign(-5), sign(5)]
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Vector(vec![Value::Int(-1), Value::String("non-negative".to_string())]));
+
+        let program = r#"
+            let i = 0; let total = 0;
+            while true {
+                i = i + 1;
+                total = total + if i > 4 { break } else { i };
+            }
+            total
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(10));
+
+        let program = r#"
+            let outer = 0;
+            while outer < 3 {
+                outer = outer + 1;
+                while true { break }
+            }
+            outer
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(3));
+    }
+
+    #[test]
+    fn test_block_scoping() {
+        let program = r#"
+            let i = 0; let total = 0;
+            while i < 3 {
+                let square = i * i;
+                total = total + square;
+                i = i + 1;
+            }
+            total
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(5));
+
+        assert_eq!(interpret("{ let hidden = 1; }\nhidden").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(interpret("let x = 1; { let x = 2; x = x + 10; } x").unwrap(), Value::Int(1));
+        assert_eq!(interpret("let x = 1; { x = 2; } x").unwrap(), Value::Int(2));
+        assert_eq!(interpret("let x = 1; let y = { let x = x + 1; x * 10 }; [x, y]").unwrap(), Value::Vector(vec![Value::Int(1), Value::Int(20)]));
+        assert_eq!(interpret("let f = fn (x) { let x = x * 2; x }; f(4)").unwrap(), Value::Int(8));
+        assert_eq!(interpret("if true { fn inner() { 1 } }\ninner()").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(interpret("let x = 1; let x = 2").unwrap_err().kind, ErrorKind::Name);
+    }
+
+    #[test]
+    fn test_repl_redeclaration() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_redeclare(true);
+
+        interpreter.interpret("let x = 1;".to_string()).unwrap();
+        interpreter.interpret("let x = x + 1;".to_string()).unwrap();
+        interpreter.interpret("fn x() { 3 }".to_string()).unwrap();
+        assert_eq!(interpreter.interpret("x()".to_string()).unwrap(), Value::Int(3));
+
+        // A failing block leaves the interpreter back in the global scope.
+        assert!(interpreter.interpret("{ let y = 1; missing }".to_string()).is_err());
+        assert_eq!(interpreter.interpret("let y = 2; y".to_string()).unwrap(), Value::Int(2));
+    }
+
+    #[test]
+    fn test_shared_closures() {
+        let program = r#"
+            fn make_counter() {
+                let count = 0;
+                fn () { count = count + 1; count }
+            }
+            let a = make_counter();
+            let b = make_counter();
+            a(); a();
+            [a(), b()]
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Vector(vec![Value::Int(3), Value::Int(1)]));
+
+        let program = r#"
+            let total = 0;
+            let add = fn (n) { total = total + n };
+            let i = 1;
+            while i <= 4 { add(i); i = i + 1; }
+            total
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(10));
+
+        let program = r#"
+            let scale = 1;
+            let scaled = fn (x) { x * scale };
+            scale = 100;
+            scaled(3)
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(300));
+
+        let program = r#"
+            fn pair() {
+                let value = 0;
+                (fn () { value }, fn (v) { value = v })
+            }
+            let p = pair();
+            p[1](42);
+            p[0]()
+        "#;
+        assert_eq!(interpret(program).unwrap(), Value::Int(42));
+
+        // Functions still round-trip through a build artifact, without their scope.
+        let function = interpret("fn id(x) { x }\nid").unwrap();
+        let restored: Value = bincode::deserialize(&bincode::serialize(&function).unwrap()).unwrap();
+        match restored {
+            Value::Function { params, .. } => assert_eq!(params, vec!["x".to_string()]),
+            value => panic!("Expected function, found: {}", value),
+        }
+    }
+
+    #[test]
+    fn test_resolver_slots() {
+        let mut ast = Parser::new("let a = 1; { let b = 2; let f = fn (x) { x + b + a }; }".to_string()).unwrap().parse().unwrap();
+        Resolver::new().resolve(&mut ast).unwrap();
+
+        let mut slots = vec![];
+        fn collect(node: &crate::ast_node::ASTNode, slots: &mut Vec<(String, Slot)>) {
+            match &node.kind {
+                ASTKind::Identifier { name, slot: Some(slot) } => slots.push((name.clone(), *slot)),
+                ASTKind::Block { statements } => statements.iter().for_each(|node| collect(node, slots)),
+                ASTKind::Let { ast } => collect(ast, slots),
+                ASTKind::Assignment { value, .. } => collect(value, slots),
+                ASTKind::FunctionDefinition { body, .. } => collect(body, slots),
+                ASTKind::BinaryOperation { left, right, .. } => {
+                    collect(left, slots);
+                    collect(right, slots);
+                },
+                _ => (),
+            }
+        }
+        collect(&ast, &mut slots);
+
+        // Inside the body: `x` is a parameter one scope out, `b` is in the block two
+        // scopes out, and `a` is the first global.
+        assert_eq!(slots, vec![
+            ("x".to_string(), Slot { depth: 1, index: 0 }),
+            ("b".to_string(), Slot { depth: 2, index: 0 }),
+            ("a".to_string(), Slot { depth: 3, index: 0 }),
+        ]);
+    }
+
+    #[test]
+    fn test_resolver_errors() {
+        let mut interpreter = Interpreter::new();
+        interpreter.init().unwrap();
+
+        // Undefined names are reported before anything runs.
+        let error = interpreter.interpret("let a = throw(\"ran\"); missing".to_string()).unwrap_err();
+        assert_eq!((error.kind, error.span.unwrap().column), (ErrorKind::Name, 23));
+        assert_eq!(interpreter.interpret("let a = 1; a".to_string()).unwrap(), Value::Int(1));
+
+        let error = interpreter.interpret("fn f() { g() }".to_string()).unwrap_err();
+        assert_eq!((error.kind, error.message.as_str()), (ErrorKind::Name, "Undefined variable: g"));
+        assert_eq!(interpreter.interpret("fn f() { g() }\nlet g = fn () { 7 };\nf()".to_string()).unwrap(), Value::Int(7));
+
+        assert_eq!(interpret("let x = 1; let y = fn () { let x = 2; x = 3 }; x = z").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(interpret("fn f(a, a) { a }").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(interpret("{ let x = 1; let x = 2; }").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(interpret("if true { let t = 1 }\nt").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(interpret("let f = fn (n) { if n == 0 { 0 } else { n + self(n - 1) } }; f(4)").unwrap(), Value::Int(10));
+    }
+
+    /// Runs a program on both backends, checks that they agree, and returns the result.
+    fn both(text: &str) -> Result<Value, LimError> {
+        let mut interpreter = Interpreter::new();
+        interpreter.init().unwrap();
+        let mut vm = Vm::new();
+        vm.init().unwrap();
+
+        let expected = interpreter.interpret(text.to_string());
+        let actual = vm.interpret(text.to_string());
+        assert_same(&expected, &actual, text);
+        actual
+    }
+
+    fn assert_same(expected: &Result<Value, LimError>, actual: &Result<Value, LimError>, text: &str) {
+        match (expected, actual) {
+            // Functions differ in representation, so only their kind is compared.
+            (Ok(expected), Ok(actual)) => assert!(
+                expected == actual || (expected.to_string() == "Function" && actual.to_string() == "Function"),
+                "{} != {} in {}", expected, actual, text
+            ),
+            (Err(expected), Err(actual)) => assert_eq!(
+                (&expected.kind, &expected.message, expected.span),
+                (&actual.kind, &actual.message, actual.span),
+                "in {}", text
+            ),
+            _ => panic!("{:?} != {:?} in {}", expected, actual, text),
+        }
+    }
+
+    #[test]
+    fn test_vm_matches_interpreter() {
+        assert_eq!(both("2 + 7 * 4 - 2 ** 3").unwrap(), Value::Int(22));
+        assert_eq!(both("9223372036854775807 + 1").unwrap(), big("9223372036854775808"));
+        assert_eq!(both("rational(1, 3) + rational(1, 6)").unwrap(), ratio(1, 2));
+        assert_eq!(both("(1, \"a\", [true, null])[2][0]").unwrap(), Value::Boolean(true));
+        assert_eq!(both("let i = 0; let s = 0; while i < 100 { s = s + i; i = i + 1; }; s").unwrap(), Value::Int(4950));
+        assert_eq!(both("let i = 0; while true { i = i + 1; if i == 5 { break } }; i").unwrap(), Value::Int(5));
+        assert_eq!(both("let i = 0; while i < 3 { i = i + 1; i * 10 }").unwrap(), Value::Int(30));
+        assert_eq!(both("let x = 1; { let x = 2; x = 3; }; x").unwrap(), Value::Int(1));
+        assert_eq!(both("false && 1 / 0 == 0 || true").unwrap(), Value::Boolean(true));
+        assert_eq!(both("if 1 > 2 { 1 }").unwrap(), Value::Null);
+        assert_eq!(both("fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }\nfib(15)").unwrap(), Value::Int(610));
+        assert_eq!(both("fn f(n) { while true { if n > 3 { return n } n = n + 1; } }\nf(0)").unwrap(), Value::Int(4));
+        assert_eq!(both("let f = fn (n) { if n == 0 { 0 } else { n + self(n - 1) } }; f(4)").unwrap(), Value::Int(10));
+        assert_eq!(both("fn id(x) { x }\nid").unwrap().to_string(), "Function");
+
+        let program = r#"
+            fn make_counter() {
+                let count = 0;
+                fn () { count = count + 1; count }
+            }
+            let a = make_counter();
+            let b = make_counter();
+            a(); a();
+            let fs = [];
+            let i = 0;
+            while i < 3 { let j = i; fs = [fs, fn () { j }]; i = i + 1; }
+            [a(), b(), fs[0][0][1](), fs[1]()]
+        "#;
+        assert_eq!(both(program).unwrap(), Value::Vector(vec![Value::Int(3), Value::Int(1), Value::Int(0), Value::Int(2)]));
+    }
+
+    #[test]
+    fn test_vm_errors() {
+        assert_eq!(both("let a = [1, 2];\na[5]").unwrap_err().kind, ErrorKind::Index);
+        assert_eq!(both("let f = fn (x) { x };\nf(1, 2)").unwrap_err().kind, ErrorKind::Arity);
+        assert_eq!(both("fn f(n) { n + true }\nlet x = 1;\nf(x)").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(both("1 && true").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(both("5(1)").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(both("1 / 0").unwrap_err().kind, ErrorKind::Division);
+        assert_eq!(both("throw(\"no\")").unwrap_err().kind, ErrorKind::Thrown);
+        assert_eq!(both("fn f() { g }\nf();\nlet g = 1").unwrap_err().kind, ErrorKind::Name);
+
+        // A failed line leaves the VM usable, with the globals defined before the error.
+        let mut vm = Vm::new();
+        vm.init().unwrap();
+        vm.set_redeclare(true);
+        assert!(vm.interpret("let a = 1; fn f() { while true { a = a + 1; { 1 / 0 } } }\nf()".to_string()).is_err());
+        assert_eq!(vm.interpret("a".to_string()).unwrap(), Value::Int(2));
+        assert_eq!(vm.interpret("let a = 10; f = fn () { a * 2 }; f()".to_string()).unwrap(), Value::Int(20));
+    }
+
+    #[test]
+    fn test_bytecode_build() {
+        let mut vm = Vm::new();
+        vm.init().unwrap();
+        let program = vm.compile("fn square(x) { x * x }\nlet v = [square(3), 2.5];\nv".to_string()).unwrap();
+
+        let restored: Program = bincode::deserialize(&bincode::serialize(&program).unwrap()).unwrap();
+        assert_eq!(restored, program);
+
+        let mut vm = Vm::new();
+        vm.init().unwrap();
+        assert_eq!(vm.run(restored).unwrap(), Value::Vector(vec![Value::Int(9), Value::Number(2.5)]));
+    }
+
+    /// Runs a program at every optimization level, on both backends and through a built
+    /// AST, checks that all agree with the unoptimized run, and returns the result.
+    fn optimized(text: &str) -> Result<Value, LimError> {
+        let expected = both(text);
+
+        for level in 1..=2 {
+            let mut interpreter = Interpreter::new();
+            interpreter.init().unwrap();
+            interpreter.set_optimization(level);
+            assert_same(&expected, &interpreter.interpret(text.to_string()), text);
+
+            let mut vm = Vm::new();
+            vm.init().unwrap();
+            vm.set_optimization(level);
+            assert_same(&expected, &vm.interpret(text.to_string()), text);
+
+            let mut builder = Interpreter::new();
+            builder.init().unwrap();
+            builder.set_optimization(level);
+            if let Ok(ast) = builder.prepare(text.to_string()) {
+                let built: ASTNode = bincode::deserialize(&bincode::serialize(&ast).unwrap()).unwrap();
+                let mut interpreter = Interpreter::new();
+                interpreter.init().unwrap();
+                assert_same(&expected, &interpreter.execute(built), text);
+            }
+        }
+
+        expected
+    }
+
+    fn prepare(text: &str, level: u8) -> ASTNode {
+        let mut interpreter = Interpreter::new();
+        interpreter.init().unwrap();
+        interpreter.set_optimization(level);
+        interpreter.prepare(text.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let statements = |text| match prepare(text, 1).kind {
+            ASTKind::Block { statements } => statements.into_iter().map(|statement| statement.kind).collect::<Vec<_>>(),
+            kind => panic!("Expected block, found: {:?}", kind),
+        };
+
+        assert_eq!(statements("2 * 3 + 4 ** 2"), vec![ASTKind::Literal(Value::Int(22))]);
+        assert_eq!(statements("!(1 < 2) || -(3) == -3"), vec![ASTKind::Literal(Value::Boolean(true))]);
+        assert_eq!(statements("if 1 > 2 { 1 }"), vec![ASTKind::Literal(Value::Null)]);
+        assert_eq!(statements("while 1 > 2 { 1 }"), vec![ASTKind::Literal(Value::Null)]);
+        assert!(matches!(&statements("if true { 1 } else { 2 }")[..], [ASTKind::Block { statements }] if statements[0].kind == ASTKind::Literal(Value::Int(1))));
+        // Failing operations are left to fail when they run.
+        assert!(matches!(statements("1 / 0")[..], [ASTKind::BinaryOperation { .. }]));
+        assert!(matches!(statements("1 && true")[..], [ASTKind::LogicalOperation { .. }]));
+        assert!(matches!(statements("let r = 2; 2 * 3.5 * r")[..], [_, ASTKind::BinaryOperation { .. }]));
+
+        assert_eq!(optimized("2 * 3.5 * 2").unwrap(), Value::Number(14.0));
+        assert_eq!(optimized("9223372036854775807 + 1 - 1").unwrap(), Value::Int(9223372036854775807));
+        assert_eq!(optimized("let x = 1; if false { x = 2 } else if 1 < 2 { x = 3 }; x").unwrap(), Value::Int(3));
+        assert_eq!(optimized("let x = 0; false && (x = 1) == 1; true || (x = 2) == 2; x").unwrap(), Value::Int(0));
+        assert_eq!(optimized("let t = true; t && 1").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(optimized("if false { 1 / 0 } else { 10 / 0 }").unwrap_err().kind, ErrorKind::Division);
+        assert_eq!(optimized("if 1 { 2 } else { 3 }").unwrap(), Value::Int(3));
+        // Names are checked before dead code is dropped.
+        assert_eq!(optimized("if false { missing }").unwrap_err().kind, ErrorKind::Name);
+    }
+
+    #[test]
+    fn test_dead_code_elimination() {
+        let program = "fn f(n) { return n * 2; n = 0; let m = n; g();\nfn g() { 1 } }\nf(4)";
+        let ast = format!("{:?}", prepare(program, 1));
+        assert!(!ast.contains("Int(0)") && ast.contains("\"g\""));
+        assert_eq!(optimized(program).unwrap(), Value::Int(8));
+
+        let program = r#"
+            let i = 0;
+            while true {
+                i = i + 1;
+                if i == 3 { break; i = 100 }
+            };
+            i
+        "#;
+        assert_eq!(optimized(program).unwrap(), Value::Int(3));
+
+        let program = r#"
+            fn find(v, x) {
+                let i = 0;
+                while i < 3 {
+                    if v[i] == x { return i; 1 / 0 }
+                    i = i + 1;
+                }
+                -1
+            }
+            (find([4, 5, 6], 5), find([4, 5, 6], 7))
+        "#;
+        assert_eq!(optimized(program).unwrap(), Value::Tuple(vec![Value::Int(1), Value::Int(-1)]));
+    }
+
+    #[test]
+    fn test_inlining() {
+        let calls = |text| format!("{:?}", prepare(text, 2)).matches("FunctionCall").count();
+        assert_eq!(calls("fn square(x) { x * x }\nsquare(3) + square(4)"), 0);
+        assert_eq!(calls("let add = fn (a, b) { a + b }; add(1, 2)"), 0);
+        // Recursive, assigned, or using outer variables: not inlined.
+        assert_eq!(calls("fn fact(n) { if n < 2 { 1 } else { n * fact(n - 1) } }\nfact(5)"), 2);
+        assert_eq!(calls("let f = fn (x) { x }; f = fn (x) { x + 1 }; f(1)"), 1);
+        assert_eq!(calls("let k = 2; fn scale(x) { x * k }\nscale(3)"), 1);
+        assert_eq!(calls("fn early(x) { if x { return 1 } 2 }\nearly(true)"), 1);
+        assert_eq!(calls("fn square(x) { x * x }\nsquare(1, 2)"), 1);
+
+        assert_eq!(optimized("fn square(x) { x * x }\nsquare(3) + square(4)").unwrap(), Value::Int(25));
+        assert_eq!(optimized("fn sub(x, y) { x - y }\nlet x = 10; let y = 3; sub(y, x)").unwrap(), Value::Int(-7));
+        assert_eq!(optimized("fn sub(x, y) { x - y }\nsub(sub(10, 1), sub(5, 3))").unwrap(), Value::Int(7));
+        assert_eq!(optimized("fn adder(n) { fn (x) { x + n } }\nadder(2)(3)").unwrap(), Value::Int(5));
+        assert_eq!(optimized("fn fact(n) { if n < 2 { 1 } else { n * fact(n - 1) } }\nfact(10)").unwrap(), Value::Int(3628800));
+        assert_eq!(optimized("fn square(x) { x * x }\nsquare(1, 2)").unwrap_err().kind, ErrorKind::Arity);
+        assert_eq!(optimized("fn half(x) { x / 0 }\nhalf(1)").unwrap_err().kind, ErrorKind::Division);
+
+        let program = r#"
+            let log = [];
+            fn pair(a, b) { (b, a) }
+            let p = pair({ log = [log, 1]; 1 }, { let a = 2; log = [log, a]; a });
+            (p, log)
+        "#;
+        assert_eq!(optimized(program).unwrap().to_string(), "((2, 1), [[[], 1], 2])");
+
+        let program = r#"
+            fn count(n) { let i = 0; while true { if i == n { break } i = i + 1; } i }
+            let total = 0;
+            let j = 0;
+            while j < 4 { total = total + count(j); j = j + 1; }
+            { let inner = fn (v) { v * 10 }; inner(total) }
+        "#;
+        assert_eq!(optimized(program).unwrap(), Value::Int(60));
+
+        // In the REPL a later line may assign to a global function, so it is not inlined.
+        let mut interpreter = Interpreter::new();
+        interpreter.init().unwrap();
+        interpreter.set_redeclare(true);
+        interpreter.set_optimization(2);
+        interpreter.interpret("let f = fn (x) { x }; let g = fn () { f(1) };".to_string()).unwrap();
+        interpreter.interpret("f = fn (x) { x * 2 };".to_string()).unwrap();
+        assert_eq!(interpreter.interpret("g()".to_string()).unwrap(), Value::Int(2));
+    }
+
+    /// Runs `test` on a thread with the stack `main` gives the default `--max-depth`.
+    fn with_stack(test: impl FnOnce() + Send + 'static) {
+        std::thread::Builder::new().stack_size(stack_size(MAX_DEPTH)).spawn(test).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let program = "fn sum(n, acc) { if n == 0 { return acc } return sum(n - 1, acc + n) }\nsum(20000, 0)";
+        assert_eq!(both(program).unwrap(), Value::Int(200010000));
+
+        let program = r#"
+            fn even(n) { if n == 0 { return true } return odd(n - 1) }
+            fn odd(n) { if n == 0 { return false } return even(n - 1) }
+            let countdown = fn (n) { while true { if n == 0 { return "done" } return self(n - 1) } };
+            (even(5001), countdown(5000))
+        "#;
+        assert_eq!(both(program).unwrap().to_string(), "(false, done)");
+
+        assert_eq!(both("fn half() { return rational(1, 2) }\nhalf()").unwrap(), ratio(1, 2));
+        assert_eq!(both("fn id(x) { x }\nreturn id(3)").unwrap(), Value::Int(3));
+        assert_eq!(both("fn f(n) { return g(n, 1) }\nfn g(a) { a }\nf(1)").unwrap_err().kind, ErrorKind::Arity);
+        assert_eq!(both("fn f(n) { return n(1) }\nf(2)").unwrap_err().kind, ErrorKind::Type);
+    }
+
+    #[test]
+    fn test_call_depth_limit() {
+        with_stack(|| {
+            let run = |text: &str, max_depth| {
+                let mut interpreter = Interpreter::new();
+                interpreter.init().unwrap();
+                interpreter.set_max_depth(max_depth);
+                let mut vm = Vm::new();
+                vm.init().unwrap();
+                vm.set_max_depth(max_depth);
+
+                let expected = interpreter.interpret(text.to_string());
+                assert_same(&expected, &vm.interpret(text.to_string()), text);
+                // The failed call leaves the interpreter usable.
+                assert_eq!(interpreter.interpret("1 + 1".to_string()).unwrap(), Value::Int(2));
+                expected
+            };
+
+            let count = "fn count(n) { if n == 0 { 0 } else { 1 + count(n - 1) } }\n";
+            assert_eq!(run(&format!("{}count(49)", count), 50).unwrap(), Value::Int(49));
+
+            let error = run(&format!("{}count(50)", count), 50).unwrap_err();
+            assert_eq!(error.kind, ErrorKind::Recursion);
+            assert_eq!(error.message, "Stack overflow in function count: more than 50 nested calls");
+            assert_eq!(error.span.unwrap().column, 42);
+
+            let error = run("let down = fn (n) { [down(n + 1)] }; down(0)", 20).unwrap_err();
+            assert_eq!(error.message, "Stack overflow in function down: more than 20 nested calls");
+            let error = run("(fn (n) { self(n) + 1 })(0)", 20).unwrap_err();
+            assert_eq!(error.message, "Stack overflow in an anonymous function: more than 20 nested calls");
+
+            // Tail calls do not count towards the limit.
+            assert_eq!(run("fn spin(n) { if n == 0 { return 0 } return spin(n - 1) }\nspin(1000)", 20).unwrap(), Value::Int(0));
+
+            // The default limit is reached without overflowing the native stack.
+            assert_eq!(both(&format!("{}count(999)", count)).unwrap(), Value::Int(999));
+            assert_eq!(both(&format!("{}count(5000)", count)).unwrap_err().kind, ErrorKind::Recursion);
+
+            // Syntax nested around the call takes native stack too: the tree backend runs
+            // out before the limit, and must say so rather than overflow.
+            let deep = |program: String, expected: Value| {
+                let mut vm = Vm::new();
+                vm.init().unwrap();
+                assert_eq!(vm.interpret(program.clone()).unwrap(), expected);
+                let mut interpreter = Interpreter::new();
+                interpreter.init().unwrap();
+                match interpreter.interpret(program) {
+                    Ok(value) => assert_eq!(value, expected),
+                    Err(error) => assert!(error.message.starts_with("Stack overflow in function f: out of stack after"), "{}", error),
+                }
+            };
+
+            let mut call = "return 1 + f(n - 1)".to_string();
+            for _ in 0..4 {
+                call = format!("for i in [1] {{ match n {{ _ => if true {{ {{ {} }} }} }} }}", call);
+            }
+            deep(format!("fn f(n) {{ if n == 0 {{ return 0 }} {} }}\nf(990)", call), Value::Int(990));
+            deep(format!("fn f(n) {{ if n == 0 {{ 0 }} else {}1 + f(n - 1){} }}\nf(999)", "{ ".repeat(40), " }".repeat(40)), Value::Int(999));
+        });
+    }
+
+    #[test]
+    fn test_for_loops() {
+        assert_eq!(optimized("let sum = 0;\nfor x in [1, 2, 3] { sum = sum + x }\nsum").unwrap(), Value::Int(6));
+        assert_eq!(optimized("let n = 0;\nfor t in (1, \"a\", null) { n = n + 1 }\nn").unwrap(), Value::Int(3));
+        assert_eq!(optimized("let s = \"\";\nfor c in \"héllo\" { s = \"${c}${s}\" }\ns").unwrap(), Value::String("olléh".to_string()));
+        assert_eq!(optimized("let sum = 0;\nfor i in 1..5 { sum = sum + i }\nfor i in 1..=5 { sum = sum + i }\nsum").unwrap(), Value::Int(25));
+        assert_eq!(optimized("for i in 5..1 { throw(\"empty\") }").unwrap(), Value::Null);
+        assert_eq!(optimized("(1 + 1..2 * 3, 0..=0)").unwrap().to_string(), "(2..6, 0..1)");
+
+        // The loop's value is its last iteration's, and every iteration binds afresh.
+        assert_eq!(optimized("for x in [1, 2, 3] { x * 10 }").unwrap(), Value::Int(30));
+        let program = "let fs = [];\nfor x in 0..2 { let y = x; fs = [fs, fn () { y }] }\nfs[0][1]() + fs[1]() * 10";
+        assert_eq!(optimized(program).unwrap(), Value::Int(10));
+        assert_eq!(optimized("let x = 7;\nfor x in [1] { let x = x + 1; x }").unwrap(), Value::Int(2));
+
+        assert_eq!(optimized("fn first(v) { for x in v { return x } \"none\" }\n(first([4, 5]), first([]))").unwrap().to_string(), "(4, none)");
+        assert_eq!(optimized("for x in 5 { x }").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(optimized("for x in 1..2.5 { x }").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(both("for x in 1..=9223372036854775807 { x }").unwrap_err().kind, ErrorKind::Overflow);
+        assert!(both("for 1 in [1] { 1 }").is_err());
+    }
+
+    #[test]
+    fn test_continue() {
+        let program = "let odd = 0;\nfor i in 0..10 { if i % 2 == 0 { continue } odd = odd + i }\nodd";
+        assert_eq!(optimized(program).unwrap(), Value::Int(25));
+        let program = "let i = 0;\nlet n = 0;\nwhile i < 10 { i = i + 1; if i > 3 { continue; n = 100 } n = n + 1 }\n(i, n)";
+        assert_eq!(optimized(program).unwrap().to_string(), "(10, 3)");
+
+        // `continue` unwinds the scopes opened inside the body, and the loop's value is null.
+        let program = "for i in 0..3 { let a = 1; { let b = 2; if i == 2 { continue } } i }";
+        assert_eq!(optimized(program).unwrap(), Value::Null);
+        let program = "let n = 0;\nfor i in 0..3 { for j in 0..3 { if j > i { continue } n = n + 1 } }\nn";
+        assert_eq!(optimized(program).unwrap(), Value::Int(6));
+
+        // Outside of a loop it ends the function, like `break`.
+        assert_eq!(optimized("fn f() { continue; 1 }\nf()").unwrap(), Value::Null);
+        assert_eq!(optimized("fn skip(x) { x + 1; continue }\nlet n = 0;\nfor i in 0..3 { skip(i); n = n + 1 }\nn").unwrap(), Value::Int(3));
+    }
+
+    #[test]
+    fn test_iteration_protocol() {
+        // A function is called until it returns null.
+        let counter = "fn count(from, to) { let i = from - 1; fn () { i = i + 1; if i < to { i } else { null } } }\n";
+        assert_eq!(optimized(&format!("{}let v = [];\nfor i in count(2, 5) {{ v = [v, i] }}\nv", counter)).unwrap().to_string(), "[[[[], 2], 3], 4]");
+        assert_eq!(optimized(&format!("{}let n = 0;\nfor i in count(0, 3) {{ for j in count(0, 3) {{ n = n + 1 }} }}\nn", counter)).unwrap(), Value::Int(9));
+        assert_eq!(optimized(&format!("{}for i in count(0, 100) {{ if i == 7 {{ break }} i }}", counter)).unwrap(), Value::Null);
+        assert_eq!(optimized(&format!("{}fn find(it) {{ for x in it {{ if x * x > 10 {{ return x }} }} }}\nfind(count(0, 10))", counter)).unwrap(), Value::Int(4));
+
+        // The function runs its own loops and calls, and can itself iterate.
+        let program = "fn sums(n) { let k = 0; fn () { if k == n { return null } k = k + 1; let s = 0; for i in 0..=k { s = s + i } s } }\nlet v = [];\nfor s in sums(4) { v = [v, s] }\nv";
+        assert_eq!(optimized(program).unwrap().to_string(), "[[[[[], 1], 3], 6], 10]");
+        let program = "fn pairs(n) { let i = 0; fn () { if i == n { return null } i = i + 1; return (i, fact(i)) } }\nfn fact(n) { if n == 0 { 1 } else { n * fact(n - 1) } }\nlet last = null;\nfor p in pairs(4) { last = p }\nlast";
+        assert_eq!(optimized(program).unwrap().to_string(), "(4, 24)");
+
+        let error = both("fn f(x) { x }\nfor x in f { x }").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Arity);
+        assert!(matches!(error.span, Some(span) if span.line == 2 && span.column == 10));
+        assert_eq!(both("for x in fn () { throw(\"stop\") } { x }").unwrap_err().kind, ErrorKind::Thrown);
+
+        with_stack(|| {
+            assert_eq!(both("let n = 0;\nfor x in fn () { n = n + 1; self() } { x }").unwrap_err().kind, ErrorKind::Recursion);
+        });
+
+        // A failed iteration leaves the VM usable.
+        let mut vm = Vm::new();
+        vm.init().unwrap();
+        assert!(vm.interpret("let g = 5;\nfor x in fn () { 1 / 0 } { x }".to_string()).is_err());
+        assert_eq!(vm.interpret("let s = 0;\nfor x in 0..4 { s = s + x }\ns + g".to_string()).unwrap(), Value::Int(11));
+    }
+
+    #[test]
+    fn test_labeled_loops() {
+        let program = "'outer: for i in 1..10 {\n for j in 1..10 {\n if i * j == 42 { break 'outer (i, j) }\n if j >= i { continue 'outer }\n }\n}";
+        assert_eq!(optimized(program).unwrap().to_string(), "(7, 6)");
+
+        let program = "let n = 0;\n'a: while n < 100 { n = n + 1; 'b: loop { if n % 2 == 0 { continue 'a } break 'b } n = n + 10 }\nn";
+        assert_eq!(optimized(program).unwrap(), Value::Int(107));
+
+        // `break` with a value gives the loop its value; a label can be reused inside.
+        assert_eq!(optimized("let i = 0;\nloop { i = i + 1; if i * i > 50 { break i } }").unwrap(), Value::Int(8));
+        assert_eq!(optimized("'a: loop { let x = 'a: loop { break 'a 1 }; break 'a x + 1 }").unwrap(), Value::Int(2));
+        assert_eq!(optimized("while true { break }").unwrap(), Value::Null);
+        assert_eq!(optimized("fn find(v) { for x in v { if x > 2 { break x } } }\n(find([1, 5, 3]), find([1]))").unwrap().to_string(), "(5, Null)");
+        assert_eq!(optimized("fn f() { break 4 }\nf() + 1").unwrap(), Value::Int(5));
+        assert_eq!(optimized("'a: for x in fn () { 1 } { for y in [1, 2] { break 'a (x, y) } }").unwrap().to_string(), "(1, 1)");
+
+        assert_eq!(both("'a: while true { break 'b }").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(both("'a: loop { fn f() { continue 'a } }").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(both("'a: { 1 }").unwrap_err().kind, ErrorKind::Parse);
+    }
+
+    #[test]
+    fn test_match() {
+        let classify = "fn f(x) { match x {\n 0 => \"zero\",\n -1 => \"minus one\",\n 1..=3 => \"small\",\n 3.5..10 => \"medium\",\n true => \"yes\",\n \"hi\" => \"greeting\",\n null => \"nothing\",\n _ => \"other\",\n} }\n";
+        let program = format!("{}(f(0), f(-1), f(3), f(9.5), f(10), f(true), f(\"hi\"), f(null), f([]))", classify);
+        assert_eq!(optimized(&program).unwrap().to_string(), "(zero, minus one, small, medium, other, yes, greeting, nothing, other)");
+        assert_eq!(optimized("match 2.0 { 2 => 1, _ => 0 }").unwrap(), Value::Int(1));
+
+        // Tuples and vectors are destructured, with `..` taking what the others leave over.
+        let program = "fn f(v) { match v {\n (a, b) if a > b => a - b,\n (a, ..rest, z) => (a, rest, z),\n [x] => x,\n [first, .., last] => first * last,\n n => n,\n} }\n(f((5, 1)), f((1, 5)), f((1, 2, 3, 4)), f([7]), f([2, 0, 4]), f([]))";
+        assert_eq!(optimized(program).unwrap().to_string(), "(4, (1, (), 5), (1, (2, 3), 4), 7, 8, [])");
+        assert_eq!(optimized("match ([1, 2], (3, [4])) { ([a, b], (c, [d])) => a + b + c + d }").unwrap(), Value::Int(10));
+        assert_eq!(optimized("match (1) { (x) => x }").unwrap(), Value::Int(1));
+
+        // Bindings are scoped to their arm, and a failed guard moves on to the next one.
+        assert_eq!(optimized("let x = 1;\nlet y = match 5 { x if x < 3 => x, x => x * 10 };\n(x, y)").unwrap().to_string(), "(1, 50)");
+        let program = "let n = 0;\nfn bump() { n = n + 1; false }\nmatch 1 { _ if bump() => 0, 1 if bump() => 0, _ => n }";
+        assert_eq!(optimized(program).unwrap(), Value::Int(2));
+
+        // Arms can leave a loop or a function.
+        let program = "let total = 0;\nfor i in 0..10 {\n match i { 3 => continue, 8 => break, _ => {} }\n total = total + i\n}\ntotal";
+        assert_eq!(optimized(program).unwrap(), Value::Int(25));
+        assert_eq!(optimized("fn f(x) { match x { 0 => return \"zero\", _ => {} } x }\n(f(0), f(1))").unwrap().to_string(), "(zero, 1)");
+
+        let error = both("match (1, 2) { (a, b, c) => a, [a, b] => b }").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Match);
+        assert!(error.message.contains("(1, 2)"));
+        assert!(matches!(error.span, Some(span) if span.line == 1 && span.column == 7));
+        assert_eq!(both("match 1 { 1 if 1 => 0 }").unwrap_err().kind, ErrorKind::Match);
+        assert_eq!(both("match 1 { (a, .., b, ..) => 0 }").unwrap_err().kind, ErrorKind::Parse);
+        assert_eq!(both("match 1 { \"a\"..\"z\" => 0 }").unwrap_err().kind, ErrorKind::Parse);
+        assert_eq!(both("match 1 { x => y }").unwrap_err().kind, ErrorKind::Name);
+    }
+
+    #[test]
+    fn test_destructuring() {
+        assert_eq!(optimized("fn divmod(a, b) { (a / b, a % b) }\nlet (q, r) = divmod(17, 5);\n(q, r)").unwrap().to_string(), "(3, 2)");
+        assert_eq!(optimized("let [first, ...rest] = [1, 2, 3];\nlet (.., last) = (4, 5, 6);\nlet _ = 7;\n(first, rest, last)").unwrap().to_string(), "(1, [2, 3], 6)");
+        assert_eq!(optimized("let (a, [b, (c,)]) = (1, [2, (3,)]);\na + b + c").unwrap(), Value::Int(6));
+        assert_eq!(optimized("let (x, y) = (1, 2);\n{ let (y, x) = (x, y); (x, y) }").unwrap().to_string(), "(2, 1)");
+
+        // Assignment evaluates the whole value first, so it can swap, and is itself that value.
+        assert_eq!(optimized("let a = 1;\nlet b = 2;\n(a, b) = (b, a);\n(a, b)").unwrap().to_string(), "(2, 1)");
+        assert_eq!(optimized("let a = 0;\nlet b = 0;\nlet v = ([a, ..b] = [1, 2, 3]);\n(a, b, v)").unwrap().to_string(), "(1, [2, 3], [1, 2, 3])");
+        assert_eq!(optimized("let n = 0;\nfn set(v) { (n, _) = v }\nset((5, 6));\nn").unwrap(), Value::Int(5));
+        assert_eq!(optimized("let a = 1;\n(a) = 2;\n(a, 3)[1] * [4, 5][1] + (a)").unwrap(), Value::Int(17));
+
+        // Parameters are patterns too, destructured before the body runs.
+        let program = "fn dist((x1, y1), (x2, y2)) { (x2 - x1) * (x2 - x1) + (y2 - y1) * (y2 - y1) }\ndist((0, 0), (3, 4))";
+        assert_eq!(optimized(program).unwrap(), Value::Int(25));
+        assert_eq!(optimized("let f = fn ([x, y], _, z) { let x = x * 10; x + y + z };\nf([1, 2], 99, 3)").unwrap(), Value::Int(15));
+        assert_eq!(optimized("fn add((a, b)) { a + b }\nadd((1, 2)) + add((3, 4))").unwrap(), Value::Int(10));
+        assert_eq!(optimized("fn head([x, ..]) { x }\nlet v = [];\nfor p in [(1, 2), (3, 4)] { v = [v, (fn ((a, b)) { a * b })(p)] }\n(head([7, 8]), v)").unwrap().to_string(), "(7, [[[], 2], 12])");
+
+        // Nothing is bound unless the whole value matches.
+        let error = both("let a = 1;\n(a, [b]) = (2, 3)").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Name);
+        let error = both("let a = 1;\nlet b = 1;\n(a, [b]) = (2, 3)").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Match);
+        assert!(error.message.contains("(2, 3)"));
+        let mut vm = Vm::new();
+        vm.init().unwrap();
+        assert!(vm.interpret("let a = 1;\nlet b = 1;\n(a, [b]) = (2, 3)".to_string()).is_err());
+        assert_eq!(vm.interpret("(a, b)".to_string()).unwrap().to_string(), "(1, 1)");
+        assert_eq!(both("fn f((a, b)) { a }\nf(1)").unwrap_err().kind, ErrorKind::Match);
+        assert_eq!(both("fn f((a, b)) { a }\nf((1, 2), 3)").unwrap_err().kind, ErrorKind::Arity);
+        assert_eq!(both("let (a, a) = (1, 2)").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(both("fn f(..rest) { rest }").unwrap_err().kind, ErrorKind::Parse);
+        assert_eq!(both("let (a, b);").unwrap_err().kind, ErrorKind::Parse);
+        let error = both("let v = [0];\nlet v[0] = 1").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Parse);
+        assert!(matches!(error.span, Some(span) if span.line == 2 && span.column == 5));
+        assert_eq!(both("let x + 1").unwrap_err().kind, ErrorKind::Parse);
+        assert_eq!(both("let x += 1").unwrap_err().kind, ErrorKind::Parse);
+    }
+
+    #[test]
+    fn test_index_assignment() {
+        assert_eq!(optimized("let v = [1, 2, 3];\nv[0] = 10;\nv[2] += 5;\nv").unwrap().to_string(), "[10, 2, 8]");
+        assert_eq!(optimized("let m = [[1, 2], [3, 4]];\nm[1][0] = 30;\nm[0][1] *= 7;\nm").unwrap().to_string(), "[[1, 14], [30, 4]]");
+        assert_eq!(optimized("let grid = [[0, 0], [0, 0]];\nfor r in 0..2 { for c in 0..2 { grid[r][c] = r * 2 + c } }\n(grid[1][0] = 9, grid)").unwrap().to_string(), "(9, [[0, 1], [9, 3]])");
+
+        // Vectors are values: a copy or a captured variable changes on its own.
+        assert_eq!(optimized("let a = [1];\nlet b = a;\nb[0] = 2;\n(a, b)").unwrap().to_string(), "([1], [2])");
+        assert_eq!(optimized("let counts = [0, 0];\nfn count(i) { counts[i] += 1 }\ncount(1);\ncount(1);\ncounts").unwrap().to_string(), "[0, 2]");
+
+        // The indices are evaluated once, before the value.
+        let program = "let i = 0;\nfn next() { i += 1; i - 1 }\nlet v = [0, 0, 0];\nv[next()] += 5;\nv[next()] = v[0] * 2 + i;\n(v, i)";
+        assert_eq!(optimized(program).unwrap().to_string(), "([5, 12, 0], 2)");
+
+        assert_eq!(both("let v = [1];\nv[1] = 2").unwrap_err().kind, ErrorKind::Index);
+        assert_eq!(both("let v = [1];\nv[0] += true").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(both("let t = (1, 2);\nt[0] = 5").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(both("let n = 1;\nn[0] = 5").unwrap_err().kind, ErrorKind::Type);
+        assert_eq!(both("w[0] = 1").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(both("[1, 2][0] = 3").unwrap_err().kind, ErrorKind::Parse);
+        assert_eq!(both("let f = fn () { [1] };\nf()[0] = 3").unwrap_err().kind, ErrorKind::Parse);
+    }
+
+    #[test]
+    fn test_compound_assignment() {
+        assert_eq!(optimized("let x = 6;\nx += 1;\nx -= 2;\nx *= 3;\nx /= 2;\nx %= 4;\nx").unwrap(), Value::Int(3));
+        assert_eq!(optimized("let b = 12;\nb &= 10;\nb |= 1;\nb ^= 3;\nb <<= 2;\nb >>= 1;\nb").unwrap(), Value::Int(20));
+        assert_eq!(optimized("let x = 1;\nlet y = (x += 2);\n(x, y, x == 3, x >= 3, x ** 2, x & 1 | 2)").unwrap().to_string(), "(3, 3, true, true, 9, 3)");
+        assert_eq!(optimized("let total = 0;\nfor i in 1..=4 { total += i * i }\ntotal").unwrap(), Value::Int(30));
+        assert_eq!(optimized("let f = 1.5;\nf *= 2;\nf").unwrap(), Value::Number(3.0));
+
+        assert_eq!(both("let x = 1;\nx /= 0").unwrap_err().kind, ErrorKind::Division);
+        assert_eq!(both("y += 1").unwrap_err().kind, ErrorKind::Name);
+        assert_eq!(both("let (a, b) = (1, 2);\n(a, b) += (1, 1)").unwrap_err().kind, ErrorKind::Parse);
     }
-}
\ No newline at end of file
+}
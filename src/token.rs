@@ -1,16 +1,28 @@
 use derive_more::Display;
+use num_bigint::BigInt;
+use num_rational::BigRational;
 use serde::{Serialize, Deserialize};
 
 use crate::value::Value;
+use crate::span::Spanned;
+use crate::error::LimError;
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize, Display)]
 pub enum Token {
     // 字面量
+    Int(i64),
+    BigInt(BigInt),
+    Rational(BigRational),
     Float(f64),
     Tuple,
-    String(Box<String>),
+    String(String),
+    #[display("interpolated string")]
+    Interpolation(Vec<Segment>),
 
-    Identifier(Box<String>),
+    Identifier(String),
+    /// A loop label, `'outer`.
+    #[display("'{_0}")]
+    Label(String),
 
     True,
     False,
@@ -24,6 +36,15 @@ pub enum Token {
     Div,
 
     Mod,
+    Pow,
+
+    // 位运算符
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    Shl,
+    Shr,
 
     // 逻辑运算符
     And,
@@ -39,9 +60,24 @@ pub enum Token {
     GreaterEqual,
     LessEqual,
 
+    // 区间运算符
+    DotDot,
+    DotDotEq,
+    // 模式中剩余元素的另一种写法 `...rest`
+    Ellipsis,
 
     // 赋值运算符
     Assign,
+    PlusAssign,
+    MinusAssign,
+    MulAssign,
+    DivAssign,
+    ModAssign,
+    BitAndAssign,
+    BitOrAssign,
+    BitXorAssign,
+    ShlAssign,
+    ShrAssign,
     Let,
     
     // 括号
@@ -58,13 +94,15 @@ pub enum Token {
     // 语句
     Statement,
     Break,
+    Continue,
 
     // 结束符
-    EOF,
+    Eof,
 
     // 函数
     FN,
     Arrow,
+    FatArrow,
     Comma,
     Return,
     Call,
@@ -75,17 +113,49 @@ pub enum Token {
     If,
     Else,
     While,
+    For,
+    In,
+    Loop,
+    Match,
+}
+
+/// A piece of an interpolated string literal: plain text, or the tokens of an
+/// embedded `${...}` expression (terminated by `Token::Eof`).
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Segment {
+    Text(String),
+    Code(Vec<Spanned<Token>>),
 }
 
 impl Token {
-    pub fn to_value(&self) -> Result<Value, String> {
+    pub fn to_value(&self) -> Result<Value, LimError> {
         Ok(match self {
+            Token::Int(v) => Value::Int(*v),
+            Token::BigInt(v) => Value::BigInt(v.clone()),
+            Token::Rational(v) => Value::Rational(v.clone()),
             Token::Float(v) => Value::Number(*v),
             Token::String(str) => Value::String(str.clone()),
             Token::True => Value::Boolean(true),
             Token::False => Value::Boolean(false),
             Token::Null => Value::Null,
-            _ => return Err(format!("Could not convert this to Value: {:?}", self.clone()))
+            _ => return Err(LimError::runtime(format!("Could not convert this to Value: {:?}", self.clone())))
+        })
+    }
+
+    /// The operator a compound assignment such as `+=` applies, if this is one.
+    pub fn compound_operator(&self) -> Option<Token> {
+        Some(match self {
+            Token::PlusAssign => Token::Plus,
+            Token::MinusAssign => Token::Minus,
+            Token::MulAssign => Token::Mul,
+            Token::DivAssign => Token::Div,
+            Token::ModAssign => Token::Mod,
+            Token::BitAndAssign => Token::BitAnd,
+            Token::BitOrAssign => Token::BitOr,
+            Token::BitXorAssign => Token::BitXor,
+            Token::ShlAssign => Token::Shl,
+            Token::ShrAssign => Token::Shr,
+            _ => return None,
         })
     }
 }
\ No newline at end of file
@@ -1,47 +1,405 @@
 use crate::ast_node::ASTNode;
-use crate::environment::Environment;
+use crate::environment::Closure;
+use crate::error::LimError;
+use crate::token::Token;
 
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{One, Pow, Signed, ToPrimitive, Zero};
 use serde::{Serialize, Deserialize};
+use std::cmp::Ordering;
 use std::fmt::{self};
 
+/// The widest result, in bits, that `<<` and `**` may produce before raising an overflow error.
+pub const MAX_BITS: u64 = 1 << 20;
+
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum Value {
+    Int(i64),
+    BigInt(BigInt),
+    Rational(BigRational),
     Number(f64),
     Boolean(bool),
-    String(Box<String>),
+    String(String),
     Function {
+        /// The name it was declared or first bound with, for error messages.
+        name: Option<String>,
         params: Vec<String>,
         body: Box<ASTNode>,
-        closure: Box<Environment>,
+        #[serde(skip)]
+        closure: Closure,
+    },
+    /// A function compiled to bytecode: an index into the VM's function table.
+    Compiled {
+        function: usize,
+        #[serde(skip)]
+        closure: Closure,
     },
     Hole(u32),
     Tuple(Vec<Value>),
     Vector(Vec<Value>),
+    /// The integers from `start` up to but excluding `end`, made by `..` and `..=`.
+    Range {
+        start: i64,
+        end: i64,
+    },
     Null,
     Nothing,
 }
 
 impl Value {
-    pub fn equal(&self, other: Self) -> Result<bool, String> {
+    pub fn equal(&self, other: Self) -> Result<bool, LimError> {
         match (self.clone(), other.clone()) {
-            (Value::Number(a), Value::Number(b)) => Ok((a - b).abs() < f64::EPSILON),
             (Value::Boolean(a), Value::Boolean(b)) => Ok(a == b),
-            _ => Err(format!("Could not compare: {:?} {:?}", *self, other))
+            _ if self.is_exact() && other.is_exact() => Ok(self.compare(&other)?.is_eq()),
+            _ if self.is_number() && other.is_number() => Ok((self.to_number()? - other.to_number()?).abs() < f64::EPSILON),
+            _ => Err(LimError::type_error(format!("Could not compare: {:?} {:?}", *self, other)))
         }
     }
 
-    pub fn to_number(&self) -> Result<f64, String> {
-        match *self {
-            Value::Number(n) => Ok(n),
-            Value::Boolean(b) => Ok(if b { 1.0 } else { 0.0 }),
-            _ => Err(format!("Could not convert to number: {:?}", self.clone())),
+    pub fn to_number(&self) -> Result<f64, LimError> {
+        match self {
+            Value::Int(n) => Ok(*n as f64),
+            Value::BigInt(n) => Ok(n.to_f64().unwrap_or(f64::NAN)),
+            Value::Rational(n) => Ok(n.to_f64().unwrap_or(f64::NAN)),
+            Value::Number(n) => Ok(*n),
+            Value::Boolean(b) => Ok(if *b { 1.0 } else { 0.0 }),
+            _ => Err(LimError::type_error(format!("Could not convert to number: {:?}", self.clone()))),
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Value::Int(_) | Value::BigInt(_) | Value::Rational(_) | Value::Number(_))
+    }
+
+    /// Integers and rationals, which are computed without rounding.
+    fn is_exact(&self) -> bool {
+        matches!(self, Value::Int(_) | Value::BigInt(_) | Value::Rational(_))
+    }
+
+    fn to_bigint(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(n) => Some(BigInt::from(*n)),
+            Value::BigInt(n) => Some(n.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            Value::Rational(n) => Some(n.clone()),
+            _ => self.to_bigint().map(BigRational::from_integer),
+        }
+    }
+
+    /// Wraps a big integer, narrowing it back to `Int` when it fits.
+    pub fn from_bigint(n: BigInt) -> Value {
+        n.to_i64().map(Value::Int).unwrap_or(Value::BigInt(n))
+    }
+
+    /// Orders two values for the relational operators. Integers and rationals compare
+    /// exactly; anything else is compared as a float.
+    pub fn compare(&self, other: &Value) -> Result<Ordering, LimError> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Ok(a.cmp(b)),
+            (Value::Int(_) | Value::BigInt(_), Value::Int(_) | Value::BigInt(_)) => Ok(self.to_bigint().cmp(&other.to_bigint())),
+            _ if self.is_exact() && other.is_exact() => Ok(self.to_rational().cmp(&other.to_rational())),
+            _ => self.to_number()?
+                .partial_cmp(&other.to_number()?)
+                .ok_or_else(|| LimError::type_error(format!("Could not compare: {:?} {:?}", self, other))),
         }
     }
 
-    pub fn get_boolean(&self) -> Result<bool, String> {
+    /// Applies an arithmetic operator. Integers stay integers (with `/` truncating) and
+    /// silently widen to big integers instead of overflowing; a rational operand makes the
+    /// result rational, and a float operand makes it a float.
+    pub fn arithmetic(&self, operator: &Token, other: &Value) -> Result<Value, LimError> {
+        match operator {
+            Token::Pow => return self.power(other),
+            Token::DotDot | Token::DotDotEq => return self.range(operator, other),
+            Token::BitAnd | Token::BitOr | Token::BitXor | Token::Shl | Token::Shr => return self.bitwise(operator, other),
+            _ => (),
+        }
+
+        match (self, other) {
+            (Value::Int(left), Value::Int(right)) => {
+                let (left, right) = (*left, *right);
+                if matches!(operator, Token::Div | Token::Mod) && right == 0 {
+                    return Err(LimError::division(if *operator == Token::Div { "Division by zero!" } else { "Modulo by zero" }));
+                }
+
+                let result = match operator {
+                    Token::Plus => left.checked_add(right),
+                    Token::Minus => left.checked_sub(right),
+                    Token::Mul => left.checked_mul(right),
+                    Token::Div => left.checked_div(right),
+                    Token::Mod => left.checked_rem(right),
+                    _ => return Err(LimError::runtime(format!("Invalid operator for binary operation: {:?}", operator))),
+                };
+
+                match result {
+                    Some(result) => Ok(Value::Int(result)),
+                    None => Value::BigInt(BigInt::from(left)).arithmetic(operator, other),
+                }
+            },
+
+            (Value::Int(_) | Value::BigInt(_), Value::Int(_) | Value::BigInt(_)) => {
+                let left = self.to_bigint().unwrap_or_default();
+                let right = other.to_bigint().unwrap_or_default();
+                if matches!(operator, Token::Div | Token::Mod) && right.is_zero() {
+                    return Err(LimError::division(if *operator == Token::Div { "Division by zero!" } else { "Modulo by zero" }));
+                }
+
+                Ok(Value::from_bigint(match operator {
+                    Token::Plus => left + right,
+                    Token::Minus => left - right,
+                    Token::Mul => left * right,
+                    Token::Div => left / right,
+                    Token::Mod => left % right,
+                    _ => return Err(LimError::runtime(format!("Invalid operator for binary operation: {:?}", operator))),
+                }))
+            },
+
+            _ if self.is_exact() && other.is_exact() => {
+                let left = self.to_rational().unwrap_or_default();
+                let right = other.to_rational().unwrap_or_default();
+                if matches!(operator, Token::Div | Token::Mod) && right.is_zero() {
+                    return Err(LimError::division(if *operator == Token::Div { "Division by zero!" } else { "Modulo by zero" }));
+                }
+
+                Ok(Value::Rational(match operator {
+                    Token::Plus => left + right,
+                    Token::Minus => left - right,
+                    Token::Mul => left * right,
+                    Token::Div => left / right,
+                    Token::Mod => left % right,
+                    _ => return Err(LimError::runtime(format!("Invalid operator for binary operation: {:?}", operator))),
+                }))
+            },
+
+            _ if self.is_number() && other.is_number() => {
+                let left = self.to_number()?;
+                let right = other.to_number()?;
+
+                Ok(Value::Number(match operator {
+                    Token::Plus => left + right,
+                    Token::Minus => left - right,
+                    Token::Mul => left * right,
+                    Token::Div => if right == 0.0 { return Err(LimError::division("Division by zero!")) } else { left / right },
+                    Token::Mod => if right == 0.0 { return Err(LimError::division("Modulo by zero")) } else { left % right },
+                    _ => return Err(LimError::runtime(format!("Invalid operator for binary operation: {:?}", operator))),
+                }))
+            },
+
+            _ => Err(LimError::type_error(format!("Invalid operands for binary operation: {:?} {:?}", self, other))),
+        }
+    }
+
+    /// Raises to a power. Integer powers of exact numbers stay exact: a negative exponent
+    /// gives a rational, and anything involving a float or a fractional exponent is a float.
+    fn power(&self, exponent: &Value) -> Result<Value, LimError> {
+        match (self, exponent) {
+            (Value::Int(base), Value::Int(exponent)) if (0..=u32::MAX as i64).contains(exponent) => {
+                if let Some(result) = base.checked_pow(*exponent as u32) {
+                    return Ok(Value::Int(result));
+                }
+            },
+            _ => (),
+        }
+
+        match exponent.to_bigint() {
+            Some(exponent) if self.is_exact() => {
+                let base = self.to_rational().unwrap_or_default();
+                if base.is_zero() && exponent.is_negative() {
+                    return Err(LimError::division("Division by zero!"));
+                }
+
+                let magnitude = if exponent.is_zero() {
+                    0
+                } else if base.is_zero() || base.abs().is_one() {
+                    // Powers of 0, 1 and -1 only depend on whether the exponent is even.
+                    if (&exponent % 2u32).is_zero() { 2 } else { 1 }
+                } else {
+                    let bits = base.numer().bits().max(base.denom().bits());
+                    exponent.abs().to_u64()
+                        .filter(|exponent| exponent.saturating_mul(bits) <= MAX_BITS)
+                        .ok_or_else(|| LimError::overflow(format!("Result of {} ** {} is too large", self, exponent)))? as u32
+                };
+
+                let result = Pow::pow(&base, magnitude);
+                let result = if exponent.is_negative() { result.recip() } else { result };
+
+                Ok(match self {
+                    Value::Rational(_) => Value::Rational(result),
+                    _ if exponent.is_negative() => Value::Rational(result),
+                    _ => Value::from_bigint(result.to_integer()),
+                })
+            },
+            _ if self.is_number() && exponent.is_number() => Ok(Value::Number(self.to_number()?.powf(exponent.to_number()?))),
+            _ => Err(LimError::type_error(format!("Invalid operands for binary operation: {:?} {:?}", self, exponent))),
+        }
+    }
+
+    /// Makes the range `self..other`, or `self..=other` which includes `other`.
+    fn range(&self, operator: &Token, other: &Value) -> Result<Value, LimError> {
+        let (Value::Int(start), Value::Int(end)) = (self, other) else {
+            return Err(LimError::type_error(format!("Range bounds must be 64-bit integers, found: {} and {}", self, other)));
+        };
+
+        let end = match operator {
+            Token::DotDotEq => end.checked_add(1).ok_or_else(|| LimError::overflow(format!("Range end is too large: {}", end)))?,
+            _ => *end,
+        };
+        Ok(Value::Range { start: *start, end })
+    }
+
+    /// Applies `&`, `|`, `^`, `<<` or `>>`. Integers behave as infinite two's complement,
+    /// so `>>` rounds towards negative infinity; `&`, `|` and `^` also accept two booleans.
+    fn bitwise(&self, operator: &Token, other: &Value) -> Result<Value, LimError> {
+        if let (Value::Boolean(left), Value::Boolean(right)) = (self, other) {
+            return match operator {
+                Token::BitAnd => Ok(Value::Boolean(left & right)),
+                Token::BitOr => Ok(Value::Boolean(left | right)),
+                Token::BitXor => Ok(Value::Boolean(left ^ right)),
+                _ => Err(LimError::type_error(format!("Invalid operands for {:?}: {} {}", operator, self, other))),
+            };
+        }
+
+        let (Some(left), Some(right)) = (self.to_bigint(), other.to_bigint()) else {
+            return Err(LimError::type_error(format!("Bitwise operands must be integers: {:?} {:?}", self, other)));
+        };
+
+        Ok(Value::from_bigint(match operator {
+            Token::BitAnd => left & right,
+            Token::BitOr => left | right,
+            Token::BitXor => left ^ right,
+            Token::Shl | Token::Shr => {
+                if right.is_negative() {
+                    return Err(LimError::runtime(format!("Negative shift amount: {}", right)));
+                }
+
+                if *operator == Token::Shr {
+                    left >> right.to_usize().unwrap_or(usize::MAX)
+                } else if left.is_zero() {
+                    left
+                } else {
+                    match right.to_u64().filter(|amount| amount.saturating_add(left.bits()) <= MAX_BITS) {
+                        Some(amount) => left << amount,
+                        None => return Err(LimError::overflow(format!("Result of {} << {} is too large", left, right))),
+                    }
+                }
+            },
+            _ => return Err(LimError::runtime(format!("Invalid operator for bitwise operation: {:?}", operator))),
+        }))
+    }
+
+    pub fn bit_not(&self) -> Result<Value, LimError> {
+        match self {
+            Value::Int(n) => Ok(Value::Int(!n)),
+            Value::BigInt(n) => Ok(Value::from_bigint(!n)),
+            _ => Err(LimError::type_error(format!("Invalid operand for bitwise NOT: {:?}", self))),
+        }
+    }
+
+    /// Applies `==`, `!=`, `>`, `<`, `>=` or `<=`.
+    pub fn relation(&self, operator: &Token, other: &Value) -> Result<bool, LimError> {
+        match operator {
+            Token::Equal => self.equal(other.clone()),
+            Token::UnEqual => Ok(!self.equal(other.clone())?),
+            _ => {
+                let ordering = self.compare(other)?;
+
+                match operator {
+                    Token::Greater => Ok(ordering.is_gt()),
+                    Token::Less => Ok(ordering.is_lt()),
+                    Token::GreaterEqual => Ok(ordering.is_ge()),
+                    Token::LessEqual => Ok(ordering.is_le()),
+                    _ => Err(LimError::runtime(format!("Invalid operator for binary operation: {:?}", operator))),
+                }
+            }
+        }
+    }
+
+    pub fn unary(&self, operator: &Token) -> Result<Value, LimError> {
+        match operator {
+            Token::Plus => Ok(self.clone()),
+            Token::Minus => self.negate(),
+            Token::Not => match self {
+                Value::Boolean(v) => Ok(Value::Boolean(!v)),
+                _ => Err(LimError::type_error(format!("Invalid operand for logical NOT: {:?}", self))),
+            },
+            Token::BitNot => self.bit_not(),
+            _ => Err(LimError::runtime(format!("Invalid operator for unary operation: {:?}", operator))),
+        }
+    }
+
+    pub fn negate(&self) -> Result<Value, LimError> {
+        match self {
+            Value::Int(n) => Ok(n.checked_neg().map(Value::Int).unwrap_or_else(|| Value::BigInt(-BigInt::from(*n)))),
+            Value::BigInt(n) => Ok(Value::from_bigint(-n)),
+            Value::Rational(n) => Ok(Value::Rational(-n)),
+            Value::Number(n) => Ok(Value::Number(-n)),
+            _ => Err(LimError::type_error(format!("Invalid operand for unary minus: {:?}", self))),
+        }
+    }
+
+    /// Converts an index operand to a position, rejecting negative and fractional numbers.
+    pub fn to_index(&self) -> Result<usize, LimError> {
+        let index = match self {
+            Value::Int(n) => usize::try_from(*n).ok(),
+            Value::BigInt(n) => n.to_usize(),
+            Value::Rational(n) if n.is_integer() => n.to_integer().to_usize(),
+            Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 && *n <= usize::MAX as f64 => Some(*n as usize),
+            Value::Rational(_) | Value::Number(_) => None,
+            _ => return Err(LimError::type_error(format!("This expression cannot be used as an index: {}", self))),
+        };
+
+        index.ok_or_else(|| LimError::index(format!("Index must be a non-negative integer, found: {}", self)))
+    }
+
+    pub fn index(&self, index: &Value) -> Result<Value, LimError> {
+        match self {
+            Value::Tuple(list) | Value::Vector(list) => {
+                let index = index.to_index()?;
+                list.get(index).cloned().ok_or_else(|| {
+                    LimError::index(format!("Index out of bounds: the len is {} but the index is {}", list.len(), index))
+                })
+            },
+            _ => Err(LimError::type_error(format!("This expression cannot be indexed: {self}"))),
+        }
+    }
+
+    /// Replaces the item that `indices` select, each indexing into the vector the one
+    /// before it selected, and returns its new value. With an `operator`, the new value is
+    /// the old one combined with `value`, as `+=` and the like do.
+    pub fn assign_index(&mut self, indices: &[Value], operator: Option<&Token>, value: Value) -> Result<Value, LimError> {
+        let mut item = self;
+        for index in indices {
+            item = match item {
+                Value::Vector(list) => {
+                    let index = index.to_index()?;
+                    let len = list.len();
+                    list.get_mut(index).ok_or_else(|| {
+                        LimError::index(format!("Index out of bounds: the len is {} but the index is {}", len, index))
+                    })?
+                },
+                Value::Tuple(_) => return Err(LimError::type_error(format!("A tuple cannot be changed: {item}"))),
+                _ => return Err(LimError::type_error(format!("This expression cannot be indexed: {item}"))),
+            };
+        }
+
+        let value = match operator {
+            Some(operator) => item.arithmetic(operator, &value)?,
+            None => value,
+        };
+        *item = value.clone();
+        Ok(value)
+    }
+
+    pub fn get_boolean(&self) -> Result<bool, LimError> {
         match *self {
             Value::Boolean(b) => Ok(b),
-            _ => Err(format!("Expected bool, found: {}!", self.clone())),
+            _ => Err(LimError::type_error(format!("Expected bool, found: {}!", self.clone()))),
         }
     }
 }
@@ -53,16 +411,22 @@ impl fmt::Display for Value {
             f, 
             "{}",
             match self {
+                Value::Int(n) => n.to_string(),
+                Value::BigInt(n) => n.to_string(),
+                Value::Rational(n) => n.to_string(),
                 Value::Number(n) => n.to_string(),
                 Value::Boolean(boolean) => boolean.to_string(),
-                Value::String(str) => *str.clone(),
+                Value::String(str) => str.clone(),
+                // `(x)` would read as a grouped `x`, so a single item keeps its comma.
+                Value::Tuple(tuple) if tuple.len() == 1 => format!("({},)", tuple[0]),
                 Value::Tuple(tuple) => {
                     format!("({})", tuple.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", "))
                 },
                 Value::Vector(vector) => {
                     format!("[{}]", vector.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", "))
                 },
-                Value::Function { .. } => "Function".to_string(),
+                Value::Range { start, end } => format!("{}..{}", start, end),
+                Value::Function { .. } | Value::Compiled { .. } => "Function".to_string(),
                 Value::Hole(v) => format!("<Builtin Function (Hole{})>", v),
                 Value::Null => "Null".to_string(),
                 Value::Nothing => String::new(),
@@ -0,0 +1,442 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::builtin::{hole_func, initialization};
+use crate::bytecode::{Function, Instruction, Program};
+use crate::compiler::Compiler;
+use crate::environment::{Closure, Environment};
+use crate::error::LimError;
+use crate::interpreter::MAX_DEPTH;
+use crate::iteration::Iteration;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::optimizer::Optimizer;
+use crate::span::Span;
+use crate::value::Value;
+
+/// A call in progress, saved while the function it called runs.
+struct Frame {
+    function: Rc<Function>,
+    /// Where to continue in `function` once the call returns.
+    ip: usize,
+    /// The stack height before the callee was pushed.
+    base: usize,
+    environment: Rc<RefCell<Environment>>,
+    loops: usize,
+}
+
+/// A loop being run: where `break` jumps to, the stack and scope `break` and `continue`
+/// unwind to, and for a `for` loop what it iterates over.
+struct Loop {
+    exit: usize,
+    stack: usize,
+    environment: Rc<RefCell<Environment>>,
+    iteration: Option<Iteration>,
+}
+
+/// Runs bytecode produced by the `Compiler`. Behaves like the `Interpreter`, with which it
+/// shares the resolver, the environments and the operations on values.
+pub struct Vm {
+    functions: Vec<Rc<Function>>,
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    resolver: Resolver,
+    optimizer: Optimizer,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    loops: Vec<Loop>,
+    max_depth: usize,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+
+        Self {
+            functions: vec![],
+            environment: globals.clone(),
+            globals,
+            resolver: Resolver::new(),
+            optimizer: Optimizer::new(),
+            stack: vec![],
+            frames: vec![],
+            loops: vec![],
+            max_depth: MAX_DEPTH,
+        }
+    }
+
+    pub fn init(&mut self) -> Result<(), LimError> {
+        initialization(&mut self.resolver, &mut self.globals.borrow_mut())
+    }
+
+    /// Lets `let` and `fn` replace a binding already declared in the same scope, so that
+    /// lines can be entered again in the REPL. Later lines may then also assign to globals,
+    /// so functions bound to them are no longer inlined.
+    pub fn set_redeclare(&mut self, redeclare: bool) {
+        self.resolver.redeclare = redeclare;
+        self.optimizer.globals = !redeclare;
+    }
+
+    /// Sets the optimizer level, from 0 (off) to 2; see `Optimizer`.
+    pub fn set_optimization(&mut self, level: u8) {
+        self.optimizer.level = level;
+    }
+
+    /// Limits how many calls may be nested, like `Interpreter::set_max_depth`.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn interpret(&mut self, text: String) -> Result<Value, LimError> {
+        let program = self.compile(text)?;
+        self.run(program)
+    }
+
+    /// Parses, resolves, optimizes and compiles source text against this VM's globals.
+    pub fn compile(&mut self, text: String) -> Result<Program, LimError> {
+        let mut ast = Parser::new(text)?.parse()?;
+        self.resolver.resolve(&mut ast)?;
+        Compiler::new().compile(&self.optimizer.optimize(ast))
+    }
+
+    pub fn run(&mut self, program: Program) -> Result<Value, LimError> {
+        // Functions from earlier programs stay alive in closures, so this program's are
+        // appended after them.
+        let offset = self.functions.len();
+        for mut function in program.functions {
+            for instruction in function.code.iter_mut() {
+                if let Instruction::Closure(index) = instruction {
+                    *index += offset;
+                }
+            }
+            self.functions.push(Rc::new(function));
+        }
+
+        let entry = self.functions[offset + program.entry].clone();
+        let result = self.execute(entry, 0);
+
+        self.stack.clear();
+        self.frames.clear();
+        self.loops.clear();
+        self.environment = self.globals.clone();
+
+        result
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("stack underflow")
+    }
+
+    fn pop_many(&mut self, count: usize) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - count)
+    }
+
+    /// Calls `callee` without arguments from inside `caller`, which continues at `ip`
+    /// afterwards, and runs the call to completion. Used for iterating over functions.
+    fn call(&mut self, callee: &Value, caller: &Rc<Function>, ip: usize, span: Span) -> Result<Value, LimError> {
+        match callee {
+            Value::Compiled { function: index, closure } => {
+                let called = self.functions[*index].clone();
+                if called.arity != 0 {
+                    return Err(LimError::arity(format!("Function expected {} arguments, but got 0", called.arity)).at(span));
+                }
+                if self.frames.len() >= self.max_depth {
+                    return Err(LimError::stack_overflow(called.name.as_deref(), self.max_depth).at(span));
+                }
+
+                let environment = Rc::new(RefCell::new(Environment {
+                    values: vec![Some(callee.clone())],
+                    parent: Some(closure.0.clone()),
+                }));
+                self.frames.push(Frame {
+                    function: caller.clone(),
+                    ip,
+                    base: self.stack.len(),
+                    environment: std::mem::replace(&mut self.environment, environment),
+                    loops: self.loops.len(),
+                });
+
+                // The frame just pushed is the one the call returns from.
+                self.execute(called, self.frames.len())
+            },
+            Value::Hole(id) => hole_func(*id, vec![]).map_err(|e| e.at(span)),
+            _ => Err(LimError::type_error(format!("Attempted to call a non-function value: {}", callee)).at(span)),
+        }
+    }
+
+    /// Runs `function` until it returns below `boundary` frames, or until the program ends.
+    fn execute(&mut self, mut function: Rc<Function>, boundary: usize) -> Result<Value, LimError> {
+        let mut ip = 0;
+
+        loop {
+            let at = ip;
+            ip += 1;
+
+            match &function.code[at] {
+                Instruction::Constant(index) => self.stack.push(function.constants[*index].clone()),
+
+                Instruction::Pop => {
+                    self.pop();
+                },
+
+                Instruction::GetVar { slot, name } => {
+                    let value = self.environment.borrow().get(&function.names[*name], *slot).map_err(|e| e.at(function.spans[at]))?;
+                    self.stack.push(value);
+                },
+
+                Instruction::SetVar { slot, name } => {
+                    let value = self.stack.last().cloned().expect("stack underflow");
+                    self.environment.borrow_mut().set(&function.names[*name], *slot, value).map_err(|e| e.at(function.spans[at]))?;
+                },
+
+                Instruction::DefineVar(index) => {
+                    let value = self.pop();
+                    self.environment.borrow_mut().define(*index, value);
+                },
+
+                Instruction::SetIndex { slot, name, indices, operator } => {
+                    let value = self.pop();
+                    let indices = self.stack.split_off(self.stack.len() - indices);
+                    let item = self.environment.borrow_mut()
+                        .update(&function.names[*name], *slot, |target| target.assign_index(&indices, operator.as_ref(), value))
+                        .map_err(|e| e.at(function.spans[at]))?;
+                    self.stack.push(item);
+                },
+
+                Instruction::Destructure { pattern, define } => {
+                    let value = match define {
+                        true => self.pop(),
+                        false => self.stack.last().cloned().expect("stack underflow"),
+                    };
+                    function.patterns[*pattern].destructure(&value, &self.environment, *define).map_err(|e| e.at(function.spans[at]))?;
+                },
+
+                Instruction::Binary(operator) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(left.arithmetic(operator, &right).map_err(|e| e.at(function.spans[at]))?);
+                },
+
+                Instruction::Compare(operator) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    self.stack.push(Value::Boolean(left.relation(operator, &right).map_err(|e| e.at(function.spans[at]))?));
+                },
+
+                Instruction::Unary(operator) => {
+                    let operand = self.pop();
+                    self.stack.push(operand.unary(operator).map_err(|e| e.at(function.spans[at]))?);
+                },
+
+                Instruction::CheckBoolean => {
+                    self.stack.last().expect("stack underflow").get_boolean().map_err(|e| e.at(function.spans[at]))?;
+                },
+
+                Instruction::Jump(target) => ip = *target,
+
+                Instruction::JumpIfFalse(target) => {
+                    if self.pop() != Value::Boolean(true) {
+                        ip = *target;
+                    }
+                },
+
+                Instruction::JumpIfFalseOrPop(target) => {
+                    if self.stack.last() == Some(&Value::Boolean(false)) {
+                        ip = *target;
+                    } else {
+                        self.pop();
+                    }
+                },
+
+                Instruction::JumpIfTrueOrPop(target) => {
+                    if self.stack.last() == Some(&Value::Boolean(true)) {
+                        ip = *target;
+                    } else {
+                        self.pop();
+                    }
+                },
+
+                Instruction::PushScope => {
+                    self.environment = Rc::new(RefCell::new(Environment::child(self.environment.clone())));
+                },
+
+                Instruction::PopScope => {
+                    let parent = self.environment.borrow().parent.clone().expect("popped the global scope");
+                    self.environment = parent;
+                },
+
+                Instruction::EnterLoop(exit) => {
+                    self.loops.push(Loop {
+                        exit: *exit,
+                        stack: self.stack.len(),
+                        environment: self.environment.clone(),
+                        iteration: None,
+                    });
+                },
+
+                Instruction::Iterate(exit) => {
+                    let iteration = Iteration::new(self.pop()).map_err(|e| e.at(function.spans[at]))?;
+                    self.loops.push(Loop {
+                        exit: *exit,
+                        stack: self.stack.len(),
+                        environment: self.environment.clone(),
+                        iteration: Some(iteration),
+                    });
+                },
+
+                Instruction::Next(done) => {
+                    let current = self.loops.len() - 1;
+                    let mut iteration = self.loops[current].iteration.take().expect("next outside of a for loop");
+                    let item = iteration.next(|callee| self.call(callee, &function, ip, function.spans[at]));
+                    self.loops[current].iteration = Some(iteration);
+
+                    match item? {
+                        Some(item) => self.stack.push(item),
+                        None => ip = *done,
+                    }
+                },
+
+                Instruction::ExitLoop => {
+                    self.loops.pop();
+                },
+
+                Instruction::Break(depth) => {
+                    let value = self.pop();
+                    self.loops.truncate(self.loops.len() - depth);
+                    let exit = self.loops.pop().expect("break outside of a loop");
+                    self.stack.truncate(exit.stack);
+                    self.environment = exit.environment;
+                    self.stack.push(value);
+                    ip = exit.exit;
+                },
+
+                Instruction::Continue { depth, next } => {
+                    self.loops.truncate(self.loops.len() - depth);
+                    let current = self.loops.last().expect("continue outside of a loop");
+                    self.stack.truncate(current.stack);
+                    self.environment = current.environment.clone();
+                    self.stack.push(Value::Null);
+                    ip = *next;
+                },
+
+                Instruction::Match { pattern, otherwise } => {
+                    let mut bindings = vec![];
+                    let value = self.stack.last().expect("stack underflow");
+                    if function.patterns[*pattern].matches(value, &mut bindings).map_err(|e| e.at(function.spans[at]))? {
+                        let mut scope = Environment::child(self.environment.clone());
+                        for (_, slot, value) in bindings {
+                            scope.define(slot.index, value);
+                        }
+                        self.environment = Rc::new(RefCell::new(scope));
+                    } else {
+                        ip = *otherwise;
+                    }
+                },
+
+                Instruction::NoMatch => {
+                    let value = self.pop();
+                    return Err(LimError::no_match(value).at(function.spans[at]));
+                },
+
+                Instruction::Closure(index) => {
+                    self.stack.push(Value::Compiled {
+                        function: *index,
+                        closure: Closure(self.environment.clone()),
+                    });
+                },
+
+                Instruction::Call(count) | Instruction::TailCall(count) => {
+                    let tail = matches!(function.code[at], Instruction::TailCall(_));
+                    let base = self.stack.len() - count - 1;
+                    let callee = self.stack[base].clone();
+
+                    match callee {
+                        Value::Compiled { function: index, ref closure } => {
+                            let called = self.functions[index].clone();
+                            if called.arity != *count {
+                                return Err(LimError::arity(format!("Function expected {} arguments, but got {}", called.arity, count)).at(function.spans[at]));
+                            }
+
+                            // Parameters first, then `self`, as the resolver numbered them.
+                            let args = self.pop_many(*count);
+                            self.stack.truncate(base);
+                            let environment = Rc::new(RefCell::new(Environment {
+                                values: args.into_iter().map(Some).chain([Some(callee.clone())]).collect(),
+                                parent: Some(closure.0.clone()),
+                            }));
+
+                            match self.frames.last() {
+                                // The running function is done with its stack, loops and scope.
+                                Some(frame) if tail => {
+                                    self.stack.truncate(frame.base);
+                                    self.loops.truncate(frame.loops);
+                                    self.environment = environment;
+                                    function = called;
+                                },
+                                _ => {
+                                    if self.frames.len() >= self.max_depth {
+                                        return Err(LimError::stack_overflow(called.name.as_deref(), self.max_depth).at(function.spans[at]));
+                                    }
+
+                                    self.frames.push(Frame {
+                                        function: std::mem::replace(&mut function, called),
+                                        ip,
+                                        base,
+                                        environment: std::mem::replace(&mut self.environment, environment),
+                                        loops: self.loops.len(),
+                                    });
+                                },
+                            }
+                            ip = 0;
+                        },
+                        Value::Hole(id) => {
+                            let args = self.pop_many(*count);
+                            self.stack.truncate(base);
+                            self.stack.push(hole_func(id, args).map_err(|e| e.at(function.spans[at]))?);
+                        },
+                        _ => return Err(LimError::type_error(format!("Attempted to call a non-function value: {}", callee)).at(function.spans[at])),
+                    }
+                },
+
+                Instruction::Return => {
+                    let value = self.pop();
+
+                    let Some(frame) = self.frames.pop() else {
+                        return Ok(value);
+                    };
+
+                    self.stack.truncate(frame.base);
+                    self.loops.truncate(frame.loops);
+                    self.environment = frame.environment;
+                    if self.frames.len() < boundary {
+                        return Ok(value);
+                    }
+                    function = frame.function;
+                    ip = frame.ip;
+                    self.stack.push(value);
+                },
+
+                Instruction::Tuple(count) => {
+                    let items = self.pop_many(*count);
+                    self.stack.push(Value::Tuple(items));
+                },
+
+                Instruction::Vector(count) => {
+                    let items = self.pop_many(*count);
+                    self.stack.push(Value::Vector(items));
+                },
+
+                Instruction::Index => {
+                    let index = self.pop();
+                    let expression = self.pop();
+                    self.stack.push(expression.index(&index).map_err(|e| e.at(function.spans[at]))?);
+                },
+
+                Instruction::Interpolate(count) => {
+                    let parts = self.pop_many(*count);
+                    self.stack.push(Value::String(parts.iter().map(Value::to_string).collect()));
+                },
+            }
+        }
+    }
+}